@@ -0,0 +1,129 @@
+use std::future::{ready, Ready};
+
+use actix_web::http::header;
+use actix_web::web::Json;
+use actix_web::{dev, web, Error, FromRequest, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::errors::ApiError;
+use crate::{AppCache, AppState};
+
+/// Extractor guarding the administration endpoints, which require the `RUS_ADMIN_TOKEN`
+/// as a bearer token. They are disabled when no token is configured
+pub struct Admin;
+
+/// Compares in constant time, to not leak the token through the response time
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub(crate) fn check_token(req: &HttpRequest) -> Result<Admin, ApiError> {
+    let expected = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| state.admin_token.to_owned())
+        .ok_or(ApiError::Forbidden)?;
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)?;
+
+    if same_token(given, &expected) {
+        Ok(Admin)
+    } else {
+        Err(ApiError::Forbidden)
+    }
+}
+
+impl FromRequest for Admin {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        ready(check_token(req))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FlushedResponse {
+    error: bool,
+    message: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/cache",
+    responses(
+        (status = 200, description = "Statistics of the cache", body = CacheStats),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn cache_stats(_: Admin, cache: web::Data<AppCache>) -> Result<impl Responder, Error> {
+    let stats = cache.cache.stats().await.map_err(ApiError::from)?;
+    Ok(Json(stats))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/cache",
+    responses(
+        (status = 200, description = "Cache flushed", body = FlushedResponse),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn flush_cache(_: Admin, cache: web::Data<AppCache>) -> Result<impl Responder, Error> {
+    cache.cache.flush().await.map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(FlushedResponse {
+        error: false,
+        message: "Cache flushed".to_owned(),
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/cache/{key}",
+    params(("key" = String, Path, description = "Short url to drop from the cache")),
+    responses(
+        (status = 200, description = "Entry purged, if it was cached", body = FlushedResponse),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn purge_cache_entry(
+    _: Admin,
+    cache: web::Data<AppCache>,
+    key: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let key = key.into_inner();
+    cache.cache.remove(&key).await.map_err(ApiError::from)?;
+    Ok(HttpResponse::Ok().json(FlushedResponse {
+        error: false,
+        message: format!("Cache entry {} purged", key),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/jobs",
+    responses(
+        (status = 200, description = "Maintenance jobs and the outcome of their last run", body = [JobStatus]),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn jobs(_: Admin, state: web::Data<AppState>) -> impl Responder {
+    Json(state.jobs.statuses())
+}
@@ -1,33 +1,63 @@
-use crate::{errors, AppCache, AppState, CreateForm, Params, DEFAULT_REDIRECTIONS_PER_PAGE};
+use crate::audit::RequestActor;
+use crate::conf::PoliciesConfig;
+use crate::errors::ApiError;
+use crate::metrics::{record_redirect, RedirectOutcome};
+use crate::payload::Payload;
+use crate::{
+    AppCache, AppState, CreateForm, LookupParams, Params, SearchParams,
+    DEFAULT_REDIRECTIONS_PER_PAGE,
+};
 use actix_files::NamedFile;
 use actix_web::web::Json;
 use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
 use entity::redirection::Model;
-use log::warn;
-use rus_core::{CreateMutation, Mutation, Query, UpdateMutation};
+use rus_core::chrono::{NaiveDateTime, Utc};
+use rus_core::errors::RusError;
+use rus_core::sea_orm::DbConn;
+use rus_core::{
+    Cache, CachedRedirection, CreateMutation, Cursor, ListOptions, Mutation, Query,
+    RedirectionFilter, UpdateMutation,
+};
 use serde::Serialize;
-use std::sync::Mutex;
+use tracing::warn;
 use url::Url;
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
-struct ListResponse {
+#[derive(Serialize, ToSchema)]
+pub struct ListResponse {
+    #[schema(value_type = Vec<Redirection>)]
     redirections: Vec<Model>,
-    page: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u64>,
     redirections_per_page: u64,
-    pages_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pages_count: Option<u64>,
+    next_cursor: Option<String>,
 }
 
-#[derive(Serialize)]
-struct CreateResponse {
+#[derive(Serialize, ToSchema)]
+pub struct CreateResponse {
     error: bool,
     message: String,
 }
 
-#[derive(Serialize)]
-struct DeletedResponse {
+#[derive(Serialize, ToSchema)]
+pub struct DeletedResponse {
     error: bool,
     message: String,
     id: i32,
+    short_url: String,
+}
+
+impl DeletedResponse {
+    pub fn new(message: &str, id: i32, short_url: String) -> DeletedResponse {
+        DeletedResponse {
+            error: false,
+            message: message.to_owned(),
+            id,
+            short_url,
+        }
+    }
 }
 const HTML_INDEX_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/templates/index.html");
 
@@ -36,183 +66,451 @@ pub async fn home() -> Result<NamedFile, Error> {
     Ok(html_index)
 }
 
+/// Validates the pagination parameters, pages start at 1
+pub(crate) fn pagination(
+    page: Option<u64>,
+    redirections_per_page: Option<u64>,
+    policies: &PoliciesConfig,
+) -> Result<(u64, u64), ApiError> {
+    pagination_of(
+        "redirections_per_page",
+        page,
+        redirections_per_page,
+        policies,
+    )
+}
+
+/// Validates the pagination parameters of a listing whose page size is given by `field`
+pub(crate) fn pagination_of(
+    field: &str,
+    page: Option<u64>,
+    per_page: Option<u64>,
+    policies: &PoliciesConfig,
+) -> Result<(u64, u64), ApiError> {
+    let page = page.unwrap_or(1);
+    if page == 0 {
+        return Err(ApiError::invalid_field("page", "Pages start at 1"));
+    }
+    let per_page = per_page.unwrap_or(DEFAULT_REDIRECTIONS_PER_PAGE);
+    if per_page == 0 {
+        return Err(ApiError::invalid_field(
+            field,
+            "At least one item per page is required",
+        ));
+    }
+    if per_page > policies.max_redirections_per_page {
+        return Err(ApiError::invalid_field(
+            field,
+            format!(
+                "At most {} items per page can be requested",
+                policies.max_redirections_per_page
+            ),
+        ));
+    }
+    Ok((page, per_page))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/redirections",
+    params(Params),
+    responses(
+        (status = 200, description = "Page of redirections", body = ListResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+    )
+)]
 pub async fn list(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, Error> {
     let conn = &data.conn;
 
     // get params
-    let params = web::Query::<Params>::from_query(req.query_string()).unwrap();
+    let params = web::Query::<Params>::from_query(req.query_string())
+        .map_err(ApiError::from)?
+        .into_inner();
+
+    let (page, redirections_per_page) =
+        pagination(params.page, params.redirections_per_page, &data.policies)?;
+
+    let options = ListOptions {
+        filter: RedirectionFilter {
+            domain: params.domain,
+            created_after: params.created_after,
+            created_before: params.created_before,
+            state: params.state,
+            ip_address: params.ip,
+        },
+        sort: params.sort.unwrap_or_default(),
+        order: params.order.unwrap_or_default(),
+    };
+
+    // a cursor switches the listing to keyset pagination
+    if let Some(after) = params.after {
+        let cursor = Cursor::decode(options.sort, &after)
+            .ok_or_else(|| ApiError::invalid_field("after", "Invalid cursor"))?;
+        let (redirections, next) =
+            Query::find_redirections_after(conn, &options, Some(&cursor), redirections_per_page)
+                .await
+                .map_err(ApiError::from)?;
+
+        return Ok(Json(ListResponse {
+            redirections,
+            page: None,
+            redirections_per_page,
+            pages_count: None,
+            next_cursor: next.map(|cursor| cursor.encode()),
+        }));
+    }
 
-    let page = params.page.unwrap_or(1);
-    let redirections_per_page = params
-        .redirections_per_page
-        .unwrap_or(DEFAULT_REDIRECTIONS_PER_PAGE);
+    let (redirections, pages_count, next) =
+        Query::find_redirections_in_page(conn, &options, page, redirections_per_page)
+            .await
+            .map_err(ApiError::from)?;
+
+    Ok(Json(ListResponse {
+        redirections,
+        page: Some(page),
+        redirections_per_page,
+        pages_count: Some(pages_count),
+        next_cursor: next.map(|cursor| cursor.encode()),
+    }))
+}
 
-    let (redirections, _pages_count) =
-        Query::find_redirections_in_page(conn, page, redirections_per_page)
+#[utoipa::path(
+    get,
+    path = "/api/v1/search",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Redirections matching the search, best matches first", body = ListResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+    )
+)]
+pub async fn search(
+    data: web::Data<AppState>,
+    params: web::Query<SearchParams>,
+) -> Result<impl Responder, Error> {
+    let params = params.into_inner();
+    let terms = params.q.trim();
+    if terms.is_empty() {
+        return Err(ApiError::invalid_field("q", "Search terms are required").into());
+    }
+    let (page, redirections_per_page) =
+        pagination(params.page, params.redirections_per_page, &data.policies)?;
+
+    let (redirections, pages_count) =
+        Query::search_redirections(&data.conn, terms, page, redirections_per_page)
             .await
-            .expect("Cannot find redirections in page");
+            .map_err(ApiError::from)?;
+
+    Ok(Json(ListResponse {
+        redirections,
+        page: Some(page),
+        redirections_per_page,
+        pages_count: Some(pages_count),
+        next_cursor: None,
+    }))
+}
+
+fn validate_form(form: &CreateForm, policies: &PoliciesConfig) -> Result<(), ApiError> {
+    validate_url(&form.long_url, policies)
+}
+
+/// Checks a destination against the link policies
+pub(crate) fn validate_url(long_url: &str, policies: &PoliciesConfig) -> Result<(), ApiError> {
+    if long_url.len() > policies.max_url_length {
+        return Err(ApiError::invalid_field(
+            "long_url",
+            format!(
+                "Urls can't be longer than {} characters",
+                policies.max_url_length
+            ),
+        ));
+    }
+    let url = Url::parse(long_url).map_err(|err| ApiError::invalid_field("long_url", err))?;
+
+    if !policies
+        .allowed_schemes
+        .iter()
+        .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+    {
+        return Err(ApiError::invalid_field(
+            "long_url",
+            format!("The '{}' scheme is not allowed", url.scheme()),
+        ));
+    }
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let blocked = policies.blocked_domains.iter().any(|domain| {
+        let domain = domain.to_lowercase();
+        host == domain || host.ends_with(&format!(".{}", domain))
+    });
+    if blocked {
+        return Err(ApiError::invalid_field(
+            "long_url",
+            format!("Links to {} are not allowed", host),
+        ));
+    }
+    Ok(())
+}
 
-    Ok(Json(redirections))
+/// Finds a redirection from its short url, or from its numeric id when `by_id` is set
+pub(crate) async fn find_redirection(
+    conn: &DbConn,
+    id: String,
+    lookup: &LookupParams,
+) -> Result<Model, ApiError> {
+    let found = if lookup.by_id.unwrap_or(false) {
+        let numeric_id = id
+            .parse::<i32>()
+            .map_err(|err| ApiError::invalid_field("id", err))?;
+        Query::find_redirection_by_id(conn, numeric_id).await?
+    } else {
+        Query::find_redirection_by_short_url(conn, id).await?
+    };
+    found.ok_or_else(|| ApiError::NotFound("Redirection not found".to_owned()))
+}
+
+/// A change committed between the read of a redirection and the fill of the cache invalidates
+/// the cache before the value read is stored, which would then stay stale. The redirection is
+/// read again once cached, and the entry dropped when it changed : a change committed after this
+/// second read invalidates the entry by itself
+async fn drop_if_stale(data: &AppState, cache: &dyn Cache, short: &str, cached: Option<&str>) {
+    let stale = match Query::find_redirection_by_short_url(&data.conn, short.to_owned()).await {
+        Ok(current) => current.as_ref().map(|model| model.long_url.as_str()) != cached,
+        // can't tell whether the entry is still valid
+        Err(_) => true,
+    };
+    if stale {
+        if let Err(e) = cache.remove(short).await {
+            warn!(
+                "Failed to drop stale short url {} from cache : {}",
+                short, e
+            );
+        }
+    }
 }
 
+async fn update_access_date(data: &AppState, short: String) {
+    if let Err(e) = Query::update_access_date(&data.conn, short.to_string()).await {
+        warn!(
+            "Failed to update last access date of {}, cause : {}",
+            short, e
+        );
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/redirections",
+    request_body(content = CreateForm, content_type = "application/json",
+        description = "Can also be sent as application/x-www-form-urlencoded"),
+    responses(
+        (status = 201, description = "Redirection created", body = CreateResponse),
+        (status = 400, description = "Invalid field", body = ErrorResponse),
+        (status = 415, description = "Unsupported content type", body = ErrorResponse),
+    )
+)]
 pub async fn create(
     data: web::Data<AppState>,
+    cache: web::Data<AppCache>,
     request: HttpRequest,
-    redirection_form: web::Form<CreateForm>,
+    actor: RequestActor,
+    redirection_form: Payload<CreateForm>,
 ) -> Result<impl Responder, Error> {
     let conn = &data.conn;
     let form = redirection_form.into_inner();
-    let url_parsing = Url::parse(form.long_url.as_str());
+    validate_form(&form, &data.policies)?;
 
-    if let Err(_err) = url_parsing {
-        return Ok(Json(CreateResponse {
-            error: true,
-            message: _err.to_string(),
-        }));
-    }
-
-    Ok(Mutation::create_redirection(
+    let mut created = Mutation::create_redirection(
         conn,
+        cache.cache.as_ref(),
+        &actor.0,
         CreateMutation::new(
             form.long_url,
             request
                 .peer_addr()
                 .map(|addr| addr.ip().to_string())
-                .unwrap_or_else(|| "".to_string()),
+                .unwrap_or_default(),
             data.link_lifetime,
-        ),
+        )
+        .with_details(form.title, form.notes),
     )
     .await
-    .map(|res| {
-        Json(CreateResponse {
-            error: false,
-            message: format!("Url {} created", res.short_url.unwrap()),
-        })
-    })
-    .unwrap_or_else(|err| {
-        Json(CreateResponse {
-            error: true,
-            message: err.to_string(),
-        })
+    .map_err(ApiError::from)?;
+    let short_url = created
+        .short_url
+        .take()
+        .ok_or(ApiError::Core(RusError::Unknown))?;
+
+    Ok(HttpResponse::Created().json(CreateResponse {
+        error: false,
+        message: format!("Url {} created", short_url),
     }))
 }
 
 pub async fn redirect(
     request: HttpRequest,
     data: web::Data<AppState>,
-    cache: web::Data<Mutex<AppCache>>,
+    cache: web::Data<AppCache>,
     id: web::Path<String>,
 ) -> Result<impl Responder, Error> {
     let short = id.into_inner();
+    let negative_ttl = cache.negative_ttl;
+    let cache = cache.cache.clone();
 
-    {
-        let cache = cache.lock().unwrap();
-
-        let redirection_opt = cache.cache.try_get(&short);
-
-        if let Some(redirection) = redirection_opt {
+    match cache.try_get(&short).await {
+        Some(CachedRedirection::Found(redirection)) => {
+            record_redirect(RedirectOutcome::Hit);
             actix_rt::spawn(async move {
-                Query::update_access_date(&data.conn, short.to_string())
-                    .await
-                    .map_err(|e| {
-                        warn!(
-                            "Failed to update last access date of {}, cause : {}",
-                            short, e
-                        );
-                    })
-                    .unwrap();
+                update_access_date(&data, short).await;
             });
 
             return Ok(HttpResponse::Found()
                 .append_header(("location", redirection))
                 .finish());
         }
+        Some(CachedRedirection::Missing) => {
+            record_redirect(RedirectOutcome::NotFound);
+            let index_file = home().await?;
+            return Ok(index_file.into_response(&request));
+        }
+        None => {}
     }
 
     let from_database = Query::find_redirection_by_short_url(&data.conn, short.to_string())
         .await
-        .map_err(errors::ApiError::from)?;
+        .map_err(ApiError::from)?;
 
-    if let Some(model) = from_database {
-        let final_url = model.long_url.to_owned();
+    let now = Utc::now().naive_utc();
+    match from_database {
+        // until the expired links job moves it to the trash
+        Some(model) if matches!(model.expiration_date, Some(expiration) if expiration <= now) => {
+            record_redirect(RedirectOutcome::Expired);
+            let index_file = home().await?;
+            Ok(index_file.into_response(&request))
+        }
+        Some(model) => {
+            record_redirect(RedirectOutcome::Miss);
+            let final_url = model.long_url.to_owned();
+            let location = final_url.clone();
 
-        actix_rt::spawn(async move {
-            let saved = cache.lock().unwrap().cache.add_entry(
-                short.to_string(),
-                model.long_url.to_string(),
-                model.expiration_date.unwrap_or_default(),
-            );
-            if let Err(e) = saved {
-                warn!("Failed to save short url {} to cache : {}", short, e)
-            }
-            Query::update_access_date(&data.conn, short.to_string())
-                .await
-                .map_err(|e| {
-                    warn!(
-                        "Failed to update last access date of {}, cause : {}",
+            actix_rt::spawn(async move {
+                let saved = cache
+                    .add_entry(
+                        short.to_string(),
+                        model.long_url.to_string(),
+                        model.expiration_date.unwrap_or(NaiveDateTime::MAX),
+                    )
+                    .await;
+                match saved {
+                    Ok(()) => drop_if_stale(&data, cache.as_ref(), &short, Some(&final_url)).await,
+                    Err(e) => warn!("Failed to save short url {} to cache : {}", short, e),
+                }
+                update_access_date(&data, short).await;
+            });
+            Ok(HttpResponse::Found()
+                .append_header(("location", location))
+                .finish())
+        }
+        None => {
+            record_redirect(RedirectOutcome::NotFound);
+            actix_rt::spawn(async move {
+                match cache.add_missing(short.to_string(), negative_ttl).await {
+                    Ok(()) => drop_if_stale(&data, cache.as_ref(), &short, None).await,
+                    Err(e) => warn!(
+                        "Failed to save missing short url {} to cache : {}",
                         short, e
-                    );
-                })
-                .unwrap();
-        });
-        Ok(HttpResponse::Found()
-            .append_header(("location", final_url))
-            .finish())
-    } else {
-        let index_file = home().await?;
-        Ok(index_file.into_response(&request))
+                    ),
+                }
+            });
+            let index_file = home().await?;
+            Ok(index_file.into_response(&request))
+        }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/redirections/{id}",
+    params(("id" = String, Path, description = "Short url of the redirection"), LookupParams),
+    responses(
+        (status = 200, description = "The redirection", body = Redirection),
+        (status = 404, description = "Unknown redirection", body = ErrorResponse),
+    )
+)]
+pub async fn get(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    lookup: web::Query<LookupParams>,
+) -> Result<impl Responder, Error> {
+    let redirection = find_redirection(&data.conn, id.into_inner(), &lookup).await?;
+
+    Ok(Json(redirection))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/redirections/{id}",
+    params(("id" = String, Path, description = "Short url of the redirection"), LookupParams),
+    request_body(content = CreateForm, content_type = "application/json",
+        description = "Can also be sent as application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Redirection updated", body = CreateResponse),
+        (status = 400, description = "Invalid field", body = ErrorResponse),
+        (status = 404, description = "Unknown redirection", body = ErrorResponse),
+    )
+)]
 pub async fn update(
     data: web::Data<AppState>,
+    cache: web::Data<AppCache>,
+    actor: RequestActor,
     id: web::Path<String>,
-    redirection_form: web::Form<CreateForm>,
+    lookup: web::Query<LookupParams>,
+    redirection_form: Payload<CreateForm>,
 ) -> Result<impl Responder, Error> {
     let conn = &data.conn;
     let form = redirection_form.into_inner();
-    let short_url = id.into_inner();
+    validate_form(&form, &data.policies)?;
+    let found = find_redirection(conn, id.into_inner(), &lookup).await?;
 
-    Ok(Mutation::update_redirection_by_id(
+    let updated = Mutation::update_redirection_by_id(
         conn,
-        UpdateMutation::new(short_url.to_owned(), form.long_url),
+        cache.cache.as_ref(),
+        &actor.0,
+        UpdateMutation::new(found.id, form.long_url),
     )
     .await
-    .map(|res| {
-        Json(CreateResponse {
-            error: false,
-            message: format!("Url {} successfully edited", res.short_url),
-        })
-    })
-    .unwrap_or_else(|err| {
-        Json(CreateResponse {
-            error: true,
-            message: err.to_string(),
-        })
+    .map_err(ApiError::from)?;
+
+    Ok(Json(CreateResponse {
+        error: false,
+        message: format!("Url {} successfully edited", updated.short_url),
     }))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/v1/redirections/{id}",
+    params(("id" = String, Path, description = "Short url of the redirection"), LookupParams),
+    responses(
+        (status = 200, description = "Redirection moved to the trash", body = DeletedResponse),
+        (status = 404, description = "Unknown redirection", body = ErrorResponse),
+    )
+)]
 pub async fn delete(
     data: web::Data<AppState>,
-    id: web::Path<i32>,
+    cache: web::Data<AppCache>,
+    actor: RequestActor,
+    id: web::Path<String>,
+    lookup: web::Query<LookupParams>,
 ) -> Result<impl Responder, Error> {
     let conn = &data.conn;
-    let id = id.into_inner();
+    let found = find_redirection(conn, id.into_inner(), &lookup).await?;
 
-    Ok(Mutation::delete_redirection(conn, id)
+    Mutation::delete_redirection(conn, cache.cache.as_ref(), &actor.0, found.id)
         .await
-        .map(|_| {
-            Json(DeletedResponse {
-                error: false,
-                message: "Deleted".to_owned(),
-                id,
-            })
-        })
-        .unwrap_or_else(|err| {
-            Json(DeletedResponse {
-                error: true,
-                message: err.to_string(),
-                id,
-            })
-        }))
+        .map_err(ApiError::from)?;
+
+    Ok(Json(DeletedResponse::new(
+        "Moved to the trash",
+        found.id,
+        found.short_url,
+    )))
 }
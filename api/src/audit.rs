@@ -0,0 +1,92 @@
+use std::future::{ready, Ready};
+
+use actix_web::web::Json;
+use actix_web::{dev, web, Error, FromRequest, HttpRequest, Responder};
+use entity::audit_event::Model as AuditEvent;
+use rus_core::{Actor, AuditFilter, Query};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::admin::{check_token, Admin};
+use crate::api::pagination_of;
+use crate::errors::ApiError;
+use crate::{AppState, AuditParams};
+
+/// Name recorded for the changes made with the admin token
+const ADMIN_KEY_NAME: &str = "admin";
+
+/// Author of a change, recorded in the audit trail: the admin API key when the request carries
+/// it, the ip address of the client otherwise
+pub struct RequestActor(pub Actor);
+
+impl FromRequest for RequestActor {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        let actor = if check_token(req).is_ok() {
+            Actor::ApiKey(ADMIN_KEY_NAME.to_owned())
+        } else {
+            Actor::Ip(
+                req.peer_addr()
+                    .map(|addr| addr.ip().to_string())
+                    .unwrap_or_default(),
+            )
+        };
+        ready(Ok(RequestActor(actor)))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditResponse {
+    #[schema(value_type = Vec<AuditEvent>)]
+    events: Vec<AuditEvent>,
+    page: u64,
+    events_per_page: u64,
+    pages_count: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/audit",
+    params(AuditParams),
+    responses(
+        (status = 200, description = "Page of the audit trail, the most recent events first", body = AuditResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn events(
+    _: Admin,
+    data: web::Data<AppState>,
+    params: web::Query<AuditParams>,
+) -> Result<impl Responder, Error> {
+    let params = params.into_inner();
+    let (page, events_per_page) = pagination_of(
+        "events_per_page",
+        params.page,
+        params.events_per_page,
+        &data.policies,
+    )?;
+    let filter = AuditFilter {
+        short_url: params.short_url,
+        action: params.action,
+        actor: params.actor,
+        after: params.after,
+        before: params.before,
+    };
+
+    let (events, pages_count) =
+        Query::find_audit_events_in_page(&data.conn, &filter, page, events_per_page)
+            .await
+            .map_err(ApiError::from)?;
+
+    Ok(Json(AuditResponse {
+        events,
+        page,
+        events_per_page,
+        pages_count,
+    }))
+}
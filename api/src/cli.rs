@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::conf::Config;
+
+#[derive(Debug, Parser)]
+#[command(name = "rus", about = "Rust Url Shortener", version)]
+pub struct Cli {
+    /// Configuration file, defaults to rus.toml when it exists
+    #[arg(long, short, env = "RUS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[arg(long)]
+    host: Option<String>,
+
+    #[arg(long)]
+    port: Option<u16>,
+
+    #[arg(long)]
+    workers: Option<usize>,
+
+    #[arg(long)]
+    database_url: Option<String>,
+
+    #[arg(long)]
+    redis_url: Option<String>,
+
+    /// Lifetime of the created links, in days
+    #[arg(long)]
+    links_lifetime: Option<i64>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print the effective one, secrets redacted
+    Check,
+}
+
+impl Cli {
+    /// The flags have the last word over the file and the environment
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(host) = &self.host {
+            config.server.host = host.to_owned();
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(workers) = self.workers {
+            config.server.workers = Some(workers);
+        }
+        if let Some(url) = &self.database_url {
+            config.database.url = Some(url.to_owned());
+        }
+        if let Some(url) = &self.redis_url {
+            config.cache.redis_url = Some(url.to_owned());
+        }
+        if let Some(lifetime) = self.links_lifetime {
+            config.links.lifetime_days = lifetime;
+        }
+    }
+}
@@ -1,48 +1,598 @@
-use log::warn;
+use std::fmt::Display;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fs, io};
 
-pub enum RusConf {
-    RedisUrl,
-    DatabaseUrl,
-    WebPort,
-    WebHost,
-    LinkDaysLifeTime,
+use rus_core::derive_more::Display;
+use rus_core::sea_orm::DbBackend;
+use rus_core::InMemoryCache;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Looked up when no configuration file is given, it's fine for it to be missing
+const DEFAULT_CONFIG_PATH: &str = "rus.toml";
+const REDACTED: &str = "***";
+/// Keeps the dates computed from the links settings far from chrono's limits
+const MAX_DAYS: i64 = 36_500;
+/// A year, longer cache entries would only serve stale links
+const MAX_TTL_SECS: i64 = 365 * 24 * 3600;
+
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    #[display(fmt = "Failed to read {} : {}", _0, _1)]
+    Read(String, io::Error),
+
+    #[display(fmt = "Invalid configuration file {} : {}", _0, _1)]
+    Parse(String, toml::de::Error),
+
+    #[display(fmt = "Invalid value '{}' for {} : {}", value, name, reason)]
+    InvalidVariable {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+
+    #[display(fmt = "Invalid configuration :\n- {}", "_0.join(\"\\n- \")")]
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Defaults to the number of physical cores
+    pub workers: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// The scheme picks the backend : postgres, sqlite or mysql
+    pub url: Option<String>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub connect_timeout_secs: u64,
+    pub idle_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// In-memory cache only when missing
+    pub redis_url: Option<String>,
+    pub redis_timeout_ms: u64,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    /// How long unknown short urls are remembered as missing
+    pub negative_ttl_secs: i64,
+    /// Lifetime of the local tier in front of redis, 0 disables it
+    pub local_ttl_secs: i64,
+    /// Number of recently accessed links loaded at startup
+    pub warmup_size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinksConfig {
+    pub lifetime_days: i64,
+    /// How long deleted and expired links stay in the trash before being purged
+    pub trash_retention_days: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    pub enabled: bool,
+    /// Delay between two runs
+    pub interval_secs: u32,
+    /// A run taking longer is cancelled and reported as timed out
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Moves the expired links to the trash
+    pub expired_links: JobConfig,
+    /// Drops the expired entries of the in-memory cache
+    pub cache_purge: JobConfig,
+    /// Permanently deletes the links kept in the trash for longer than the retention
+    pub trash_purge: JobConfig,
+}
+
+/// Rules the links and the requests must follow
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoliciesConfig {
+    pub allowed_schemes: Vec<String>,
+    pub max_url_length: usize,
+    /// Destinations that can't be shortened, subdomains included
+    pub blocked_domains: Vec<String>,
+    pub max_redirections_per_page: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Bearer token of the administration endpoints, disabled when missing
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    /// Collector the traces are exported to with OTLP over gRPC, disabled when missing.
+    /// Requires the `otlp` feature
+    pub otlp_endpoint: Option<String>,
+    /// Name of the service in the traces
+    pub service_name: String,
+}
+
+/// Configuration of the whole application. Each layer overrides the previous one :
+/// defaults, configuration file, `RUS_*` environment variables and command line flags
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub links: LinksConfig,
+    pub jobs: JobsConfig,
+    pub policies: PoliciesConfig,
+    pub admin: AdminConfig,
+    pub tracing: TracingConfig,
 }
 
-impl RusConf {
-    fn name(&self) -> &str {
-        match *self {
-            RusConf::RedisUrl => "RUS_REDIS_URL",
-            RusConf::DatabaseUrl => "RUS_DATABASE_URL",
-            RusConf::WebPort => "RUS_PORT",
-            RusConf::WebHost => "RUS_HOST",
-            RusConf::LinkDaysLifeTime => "RUS_LINKS_LIFETIME",
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "0.0.0.0".to_owned(),
+            port: 8000,
+            workers: None,
         }
     }
+}
 
-    pub fn get(&self) -> Option<String> {
-        std::env::var(self.name()).ok()
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: None,
+            max_connections: 10,
+            min_connections: 1,
+            connect_timeout_secs: 8,
+            idle_timeout_secs: 600,
+        }
     }
+}
 
-    pub fn get_i64_or(&self, default: i64) -> i64 {
-        self.get()
-            .and_then(|val| {
-                val.parse::<i64>()
-                    .map_err(|err| {
-                        warn!("{} is not a valid int ({})", val, err);
-                    })
-                    .ok()
-            })
-            .unwrap_or(default)
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            redis_url: None,
+            redis_timeout_ms: 500,
+            max_entries: 10_000,
+            max_bytes: 16 * 1024 * 1024,
+            negative_ttl_secs: 30,
+            local_ttl_secs: 10,
+            warmup_size: 0,
+        }
     }
+}
 
-    pub fn get_or(&self, default: String) -> String {
-        let var_name = self.name();
-        std::env::var(var_name).unwrap_or_else(|err| {
-            warn!(
-                "Failed to get env variable {} (err), using default ({}) instead",
-                var_name, err
-            );
-            default
+impl Default for LinksConfig {
+    fn default() -> Self {
+        LinksConfig {
+            lifetime_days: 90,
+            trash_retention_days: 30,
+        }
+    }
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        JobConfig {
+            enabled: true,
+            interval_secs: 60,
+            timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            expired_links: JobConfig::default(),
+            cache_purge: JobConfig::default(),
+            trash_purge: JobConfig {
+                interval_secs: 3600,
+                ..JobConfig::default()
+            },
+        }
+    }
+}
+
+impl Default for PoliciesConfig {
+    fn default() -> Self {
+        PoliciesConfig {
+            allowed_schemes: vec!["http".to_owned(), "https".to_owned()],
+            max_url_length: 2048,
+            blocked_domains: vec![],
+            max_redirections_per_page: 1000,
+        }
+    }
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            otlp_endpoint: None,
+            service_name: "rus".to_owned(),
+        }
+    }
+}
+
+fn parse<T: FromStr>(name: &'static str, value: String) -> Result<T, ConfigError>
+where
+    T::Err: Display,
+{
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|err| ConfigError::InvalidVariable {
+            reason: err.to_string(),
+            name,
+            value,
         })
+}
+
+/// Reads the environment variables overriding the configuration
+struct Variables<F: Fn(&str) -> Option<String>>(F);
+
+impl<F: Fn(&str) -> Option<String>> Variables<F> {
+    fn set<T: FromStr>(&self, name: &'static str, target: &mut T) -> Result<(), ConfigError>
+    where
+        T::Err: Display,
+    {
+        if let Some(value) = (self.0)(name) {
+            *target = parse(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn set_option<T: FromStr>(
+        &self,
+        name: &'static str,
+        target: &mut Option<T>,
+    ) -> Result<(), ConfigError>
+    where
+        T::Err: Display,
+    {
+        if let Some(value) = (self.0)(name) {
+            *target = Some(parse(name, value)?);
+        }
+        Ok(())
+    }
+
+    /// Comma separated values
+    fn set_list(&self, name: &'static str, target: &mut Vec<String>) {
+        if let Some(value) = (self.0)(name) {
+            *target = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+    }
+}
+
+/// The database backend is picked from the scheme of the url, its driver must have been
+/// enabled at compile time through the matching `sqlx-*` feature
+pub fn database_backend(url: &str) -> Result<DbBackend, String> {
+    let scheme = url.split(':').next().unwrap_or_default();
+    let (backend, enabled) = match scheme {
+        "postgres" | "postgresql" => (DbBackend::Postgres, cfg!(feature = "sqlx-postgres")),
+        "sqlite" => (DbBackend::Sqlite, cfg!(feature = "sqlx-sqlite")),
+        "mysql" => (DbBackend::MySql, cfg!(feature = "sqlx-mysql")),
+        _ => return Err(format!("Unsupported database url scheme '{}'", scheme)),
+    };
+    if enabled {
+        Ok(backend)
+    } else {
+        Err(format!(
+            "Rus was built without support for '{}' databases",
+            scheme
+        ))
+    }
+}
+
+/// Hides the password of an url, if any
+fn redact_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut parsed) if parsed.password().is_some() => {
+            parsed.set_password(Some(REDACTED)).ok();
+            parsed.to_string()
+        }
+        Ok(_) => url.to_owned(),
+        Err(_) => REDACTED.to_owned(),
+    }
+}
+
+impl Config {
+    pub fn from_toml(content: &str, path: &str) -> Result<Config, ConfigError> {
+        toml::from_str(content).map_err(|err| ConfigError::Parse(path.to_owned(), err))
+    }
+
+    /// Reads the given file, or `rus.toml` when it exists, then applies the environment variables
+    pub fn load(
+        path: Option<&Path>,
+        variables: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(path) => Some(path.to_owned()),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()),
+        };
+        let mut config = match path {
+            Some(path) => {
+                let name = path.display().to_string();
+                let content = fs::read_to_string(&path)
+                    .map_err(|err| ConfigError::Read(name.clone(), err))?;
+                Config::from_toml(&content, &name)?
+            }
+            None => Config::default(),
+        };
+        config.apply_variables(variables)?;
+        Ok(config)
+    }
+
+    pub fn apply_variables(
+        &mut self,
+        variables: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), ConfigError> {
+        let vars = Variables(variables);
+
+        vars.set("RUS_HOST", &mut self.server.host)?;
+        vars.set("RUS_PORT", &mut self.server.port)?;
+        vars.set_option("RUS_WORKERS", &mut self.server.workers)?;
+
+        vars.set_option("RUS_DATABASE_URL", &mut self.database.url)?;
+        vars.set("RUS_DB_MAX_CONNECTIONS", &mut self.database.max_connections)?;
+        vars.set("RUS_DB_MIN_CONNECTIONS", &mut self.database.min_connections)?;
+        vars.set(
+            "RUS_DB_CONNECT_TIMEOUT_SECS",
+            &mut self.database.connect_timeout_secs,
+        )?;
+        vars.set(
+            "RUS_DB_IDLE_TIMEOUT_SECS",
+            &mut self.database.idle_timeout_secs,
+        )?;
+
+        vars.set_option("RUS_REDIS_URL", &mut self.cache.redis_url)?;
+        vars.set("RUS_REDIS_TIMEOUT_MS", &mut self.cache.redis_timeout_ms)?;
+        vars.set("RUS_CACHE_MAX_ENTRIES", &mut self.cache.max_entries)?;
+        vars.set("RUS_CACHE_MAX_BYTES", &mut self.cache.max_bytes)?;
+        vars.set(
+            "RUS_CACHE_NEGATIVE_TTL_SECS",
+            &mut self.cache.negative_ttl_secs,
+        )?;
+        vars.set("RUS_CACHE_LOCAL_TTL_SECS", &mut self.cache.local_ttl_secs)?;
+        vars.set("RUS_CACHE_WARMUP_SIZE", &mut self.cache.warmup_size)?;
+
+        vars.set("RUS_LINKS_LIFETIME", &mut self.links.lifetime_days)?;
+        vars.set(
+            "RUS_LINKS_TRASH_RETENTION_DAYS",
+            &mut self.links.trash_retention_days,
+        )?;
+
+        vars.set(
+            "RUS_JOBS_EXPIRED_LINKS_ENABLED",
+            &mut self.jobs.expired_links.enabled,
+        )?;
+        vars.set(
+            "RUS_JOBS_EXPIRED_LINKS_INTERVAL_SECS",
+            &mut self.jobs.expired_links.interval_secs,
+        )?;
+        vars.set(
+            "RUS_JOBS_EXPIRED_LINKS_TIMEOUT_SECS",
+            &mut self.jobs.expired_links.timeout_secs,
+        )?;
+        vars.set(
+            "RUS_JOBS_CACHE_PURGE_ENABLED",
+            &mut self.jobs.cache_purge.enabled,
+        )?;
+        vars.set(
+            "RUS_JOBS_CACHE_PURGE_INTERVAL_SECS",
+            &mut self.jobs.cache_purge.interval_secs,
+        )?;
+        vars.set(
+            "RUS_JOBS_CACHE_PURGE_TIMEOUT_SECS",
+            &mut self.jobs.cache_purge.timeout_secs,
+        )?;
+        vars.set(
+            "RUS_JOBS_TRASH_PURGE_ENABLED",
+            &mut self.jobs.trash_purge.enabled,
+        )?;
+        vars.set(
+            "RUS_JOBS_TRASH_PURGE_INTERVAL_SECS",
+            &mut self.jobs.trash_purge.interval_secs,
+        )?;
+        vars.set(
+            "RUS_JOBS_TRASH_PURGE_TIMEOUT_SECS",
+            &mut self.jobs.trash_purge.timeout_secs,
+        )?;
+
+        vars.set_list("RUS_ALLOWED_SCHEMES", &mut self.policies.allowed_schemes);
+        vars.set("RUS_MAX_URL_LENGTH", &mut self.policies.max_url_length)?;
+        vars.set_list("RUS_BLOCKED_DOMAINS", &mut self.policies.blocked_domains);
+        vars.set(
+            "RUS_MAX_REDIRECTIONS_PER_PAGE",
+            &mut self.policies.max_redirections_per_page,
+        )?;
+
+        vars.set_option("RUS_ADMIN_TOKEN", &mut self.admin.token)?;
+
+        vars.set_option("RUS_OTLP_ENDPOINT", &mut self.tracing.otlp_endpoint)?;
+        vars.set("RUS_SERVICE_NAME", &mut self.tracing.service_name)?;
+        Ok(())
+    }
+
+    /// Reports every invalid setting at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = vec![];
+
+        if self.server.workers == Some(0) {
+            errors.push("server.workers must be at least 1".to_owned());
+        }
+
+        match &self.database.url {
+            None => errors
+                .push("database.url is required, it can be set with RUS_DATABASE_URL".to_owned()),
+            Some(url) => {
+                if let Err(err) = database_backend(url) {
+                    errors.push(format!("database.url : {}", err));
+                }
+            }
+        }
+        if self.database.max_connections == 0 {
+            errors.push("database.max_connections must be at least 1".to_owned());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            errors.push(
+                "database.min_connections can't be greater than database.max_connections"
+                    .to_owned(),
+            );
+        }
+
+        if let Some(url) = &self.cache.redis_url {
+            if let Err(err) = redis_url_scheme(url) {
+                errors.push(format!("cache.redis_url : {}", err));
+            }
+        }
+        if self.cache.redis_timeout_ms == 0 {
+            errors.push("cache.redis_timeout_ms must be at least 1".to_owned());
+        }
+        match NonZeroUsize::new(self.cache.max_entries) {
+            None => errors.push("cache.max_entries must be at least 1".to_owned()),
+            Some(max_entries) => {
+                let shards = InMemoryCache::shards_count(max_entries);
+                if self.cache.max_bytes < shards {
+                    errors.push(format!(
+                        "cache.max_bytes must be at least {}, the number of cache shards",
+                        shards
+                    ));
+                }
+            }
+        }
+        if self.cache.negative_ttl_secs < 0 {
+            errors.push("cache.negative_ttl_secs can't be negative".to_owned());
+        } else if self.cache.negative_ttl_secs > MAX_TTL_SECS {
+            errors.push(format!(
+                "cache.negative_ttl_secs can't be greater than {}",
+                MAX_TTL_SECS
+            ));
+        }
+        if self.cache.local_ttl_secs < 0 {
+            errors.push("cache.local_ttl_secs can't be negative".to_owned());
+        } else if self.cache.local_ttl_secs > MAX_TTL_SECS {
+            errors.push(format!(
+                "cache.local_ttl_secs can't be greater than {}",
+                MAX_TTL_SECS
+            ));
+        }
+
+        if self.links.lifetime_days < 1 {
+            errors.push("links.lifetime_days must be at least 1".to_owned());
+        } else if self.links.lifetime_days > MAX_DAYS {
+            errors.push(format!(
+                "links.lifetime_days can't be greater than {}",
+                MAX_DAYS
+            ));
+        }
+        if self.links.trash_retention_days < 0 {
+            errors.push("links.trash_retention_days can't be negative".to_owned());
+        } else if self.links.trash_retention_days > MAX_DAYS {
+            errors.push(format!(
+                "links.trash_retention_days can't be greater than {}",
+                MAX_DAYS
+            ));
+        }
+
+        for (name, job) in [
+            ("expired_links", &self.jobs.expired_links),
+            ("cache_purge", &self.jobs.cache_purge),
+            ("trash_purge", &self.jobs.trash_purge),
+        ] {
+            if job.interval_secs == 0 {
+                errors.push(format!("jobs.{}.interval_secs must be at least 1", name));
+            }
+            if job.timeout_secs == 0 {
+                errors.push(format!("jobs.{}.timeout_secs must be at least 1", name));
+            }
+        }
+
+        if self.policies.allowed_schemes.is_empty() {
+            errors.push("policies.allowed_schemes can't be empty".to_owned());
+        }
+        if self.policies.max_url_length == 0 {
+            errors.push("policies.max_url_length must be at least 1".to_owned());
+        }
+        if self.policies.max_redirections_per_page == 0 {
+            errors.push("policies.max_redirections_per_page must be at least 1".to_owned());
+        }
+
+        if matches!(&self.admin.token, Some(token) if token.trim().is_empty()) {
+            errors.push("admin.token can't be blank".to_owned());
+        }
+
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            if let Err(err) = otlp_endpoint(endpoint) {
+                errors.push(format!("tracing.otlp_endpoint : {}", err));
+            }
+        }
+        if self.tracing.service_name.trim().is_empty() {
+            errors.push("tracing.service_name can't be blank".to_owned());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    /// Copy of the configuration that is safe to print
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        config.database.url = config.database.url.as_deref().map(redact_url);
+        config.cache.redis_url = config.cache.redis_url.as_deref().map(redact_url);
+        config.admin.token = config.admin.token.map(|_| REDACTED.to_owned());
+        config
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("The configuration can always be serialized")
+    }
+}
+
+fn otlp_endpoint(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|err| err.to_string())?;
+    match parsed.scheme() {
+        "http" | "https" => {}
+        scheme => return Err(format!("Unsupported OTLP endpoint scheme '{}'", scheme)),
+    }
+    if cfg!(feature = "otlp") {
+        Ok(())
+    } else {
+        Err("Rus was built without the 'otlp' feature".to_owned())
+    }
+}
+
+fn redis_url_scheme(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|err| err.to_string())?;
+    match parsed.scheme() {
+        "redis" | "rediss" | "redis+unix" | "unix" => Ok(()),
+        scheme => Err(format!("Unsupported redis url scheme '{}'", scheme)),
     }
 }
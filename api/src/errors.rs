@@ -1,8 +1,11 @@
 use actix_http::body::BoxBody;
 use actix_http::StatusCode;
-use actix_web::error::ResponseError;
-use actix_web::http::header::ContentType;
+use actix_web::error::{
+    JsonPayloadError, PathError, QueryPayloadError, ResponseError, UrlencodedError,
+};
 use actix_web::HttpResponse;
+use serde::Serialize;
+use utoipa::ToSchema;
 
 use migration::DbErr;
 use rus_core::derive_more::{Display, Error};
@@ -13,20 +16,108 @@ use rus_core::redis::RedisError;
 pub enum ApiError {
     #[display(fmt = "Interal error")]
     Core(RusError),
-    // NotFound,
+
+    #[display(fmt = "{}", _0)]
+    NotFound(#[error(not(source))] String),
+
+    #[display(fmt = "{}", message)]
+    BadRequest {
+        field: Option<String>,
+        message: String,
+    },
+
+    #[display(fmt = "{}", _0)]
+    Conflict(#[error(not(source))] String),
+
+    #[display(fmt = "Authentication required")]
+    Unauthorized,
+
+    #[display(fmt = "Access forbidden")]
+    Forbidden,
+
+    #[display(fmt = "Unsupported content type '{}'", _0)]
+    UnsupportedMediaType(#[error(not(source))] String),
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse<'a> {
+    error: bool,
+    /// Stable identifier of the error, e.g. `not_found`
+    code: &'a str,
+    message: String,
+    /// Name of the invalid field, for validation errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'a str>,
+}
+
+impl ApiError {
+    pub fn invalid_field(field: &str, message: impl ToString) -> Self {
+        ApiError::BadRequest {
+            field: Some(field.to_owned()),
+            message: message.to_string(),
+        }
+    }
+
+    /// Stable, machine-readable identifier of the error, matching `RusError::code` for core errors
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Core(err) => err.code(),
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest { .. } => "bad_request",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+        }
+    }
+
+    fn field(&self) -> Option<&str> {
+        match self {
+            ApiError::BadRequest { field, .. } => field.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Serde errors only name the offending field between backticks, e.g. "missing field `long_url`"
+fn offending_field(message: &str) -> Option<String> {
+    if !message.contains(" field `") {
+        return None;
+    }
+    message.split('`').nth(1).map(str::to_owned)
 }
 
 impl ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse<BoxBody> {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::json())
-            .body(self.to_string())
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            error: true,
+            code: self.code(),
+            message: self.to_string(),
+            field: self.field(),
+        })
     }
 
     fn status_code(&self) -> StatusCode {
         match *self {
             ApiError::Core(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            // ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest { .. } => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        }
+    }
+}
+
+impl From<RusError> for ApiError {
+    fn from(err: RusError) -> Self {
+        match err {
+            RusError::NotFound(_) => ApiError::NotFound(err.to_string()),
+            RusError::Conflict(_) => ApiError::Conflict(err.to_string()),
+            RusError::Unauthorized => ApiError::Unauthorized,
+            RusError::Forbidden => ApiError::Forbidden,
+            _ => ApiError::Core(err),
         }
     }
 }
@@ -42,3 +133,55 @@ impl From<RedisError> for ApiError {
         ApiError::Core(RusError::from(err))
     }
 }
+
+impl From<JsonPayloadError> for ApiError {
+    fn from(err: JsonPayloadError) -> Self {
+        let message = match &err {
+            JsonPayloadError::Deserialize(details) => details.to_string(),
+            _ => err.to_string(),
+        };
+        ApiError::BadRequest {
+            field: offending_field(&message),
+            message,
+        }
+    }
+}
+
+impl From<UrlencodedError> for ApiError {
+    fn from(err: UrlencodedError) -> Self {
+        let message = match &err {
+            UrlencodedError::Parse(details) => details.to_string(),
+            _ => err.to_string(),
+        };
+        ApiError::BadRequest {
+            field: offending_field(&message),
+            message,
+        }
+    }
+}
+
+impl From<QueryPayloadError> for ApiError {
+    fn from(err: QueryPayloadError) -> Self {
+        let message = match &err {
+            QueryPayloadError::Deserialize(details) => details.to_string(),
+            _ => err.to_string(),
+        };
+        ApiError::BadRequest {
+            field: offending_field(&message),
+            message,
+        }
+    }
+}
+
+impl From<PathError> for ApiError {
+    fn from(err: PathError) -> Self {
+        let message = match &err {
+            PathError::Deserialize(details) => details.to_string(),
+            _ => err.to_string(),
+        };
+        ApiError::BadRequest {
+            field: offending_field(&message),
+            message,
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::future::Future;
+use std::time::{Duration as StdDuration, Instant};
+
+use actix_rt::time::timeout;
+use actix_web::web::Json;
+use actix_web::{web, HttpResponse, Responder};
+use migration::{Migrator, MigratorTrait};
+use rus_core::errors::RusError;
+use rus_core::sea_orm::{ConnectionTrait, Statement};
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{AppCache, AppState};
+
+/// A probe must answer before the orchestrator gives up on it, a stuck dependency is reported
+/// as down instead
+const CHECK_TIMEOUT: StdDuration = StdDuration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    /// Redis is unreachable, the instance still serves the redirections from the database
+    Degraded,
+    Down,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AliveResponse {
+    status: CheckStatus,
+}
+
+/// Outcome of the check of a dependency
+#[derive(Serialize, ToSchema)]
+pub struct DependencyCheck {
+    status: CheckStatus,
+    latency_ms: f64,
+    /// Kind of the failure, the details are logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MigrationsCheck {
+    #[serde(flatten)]
+    check: DependencyCheck,
+    /// Migrations known to this instance and not applied yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadyResponse {
+    /// Up when every dependency is, degraded when only redis is down
+    status: CheckStatus,
+    database: DependencyCheck,
+    /// Only checked when the cache is held by redis, degraded when it is down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redis: Option<DependencyCheck>,
+    migrations: MigrationsCheck,
+}
+
+/// Runs a check within `CHECK_TIMEOUT`, and measures it
+async fn check<T>(
+    name: &str,
+    probe: impl Future<Output = Result<T, RusError>>,
+) -> (DependencyCheck, Option<T>) {
+    let started = Instant::now();
+    let result = timeout(CHECK_TIMEOUT, probe).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let (error, value) = match result {
+        Ok(Ok(value)) => (None, Some(value)),
+        Ok(Err(err)) => {
+            warn!("Readiness check of {} failed : {}", name, err.name());
+            (Some(err.code()), None)
+        }
+        Err(_) => {
+            warn!("Readiness check of {} timed out", name);
+            (Some("timeout"), None)
+        }
+    };
+    let status = match error {
+        None => CheckStatus::Up,
+        Some(_) => CheckStatus::Down,
+    };
+    (
+        DependencyCheck {
+            status,
+            latency_ms,
+            error,
+        },
+        value,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "The process is alive", body = AliveResponse),
+    )
+)]
+pub async fn healthz() -> impl Responder {
+    Json(AliveResponse {
+        status: CheckStatus::Up,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "The database is up, redis may be degraded", body = ReadyResponse),
+        (status = 503, description = "The database is down, or not migrated", body = ReadyResponse),
+    )
+)]
+pub async fn readyz(data: web::Data<AppState>, cache: web::Data<AppCache>) -> impl Responder {
+    let conn = &data.conn;
+    let (database, _) = check("the database", async {
+        let backend = conn.get_database_backend();
+        conn.execute(Statement::from_string(backend, "SELECT 1".to_owned()))
+            .await?;
+        Ok(())
+    })
+    .await;
+
+    let (redis, remote) = check("redis", async {
+        Ok(cache.cache.ping().await.transpose()?.is_some())
+    })
+    .await;
+    // the in-memory caches have nothing to reach, only a failed ping comes from redis
+    let mut redis = (remote != Some(false)).then_some(redis);
+    if let Some(redis) = redis.as_mut() {
+        if redis.status == CheckStatus::Down {
+            redis.status = CheckStatus::Degraded;
+        }
+    }
+
+    let (migrations, pending) = check("the migrations", async {
+        Ok(Migrator::get_pending_migrations(conn).await?.len())
+    })
+    .await;
+    let mut migrations = MigrationsCheck {
+        check: migrations,
+        pending,
+    };
+    if matches!(pending, Some(pending) if pending > 0) {
+        migrations.check.status = CheckStatus::Down;
+        migrations.check.error = Some("pending_migrations");
+    }
+
+    let ready = database.status == CheckStatus::Up && migrations.check.status == CheckStatus::Up;
+    let status = if !ready {
+        CheckStatus::Down
+    } else if redis.iter().any(|redis| redis.status != CheckStatus::Up) {
+        CheckStatus::Degraded
+    } else {
+        CheckStatus::Up
+    };
+    let response = ReadyResponse {
+        status,
+        database,
+        redis,
+        migrations,
+    };
+    if ready {
+        HttpResponse::Ok().json(response)
+    } else {
+        HttpResponse::ServiceUnavailable().json(response)
+    }
+}
@@ -0,0 +1,88 @@
+use actix_web::web::Json;
+use actix_web::{web, Error, Responder};
+use entity::redirection_version::Model as RedirectionVersion;
+use rus_core::{Mutation, Query};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api::{find_redirection, validate_url};
+use crate::audit::RequestActor;
+use crate::errors::ApiError;
+use crate::{AppCache, AppState, LookupParams};
+
+#[derive(Serialize, ToSchema)]
+pub struct HistoryResponse {
+    id: i32,
+    short_url: String,
+    /// Every destination of the redirection, the current one first
+    #[schema(value_type = Vec<RedirectionVersion>)]
+    versions: Vec<RedirectionVersion>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/redirections/{id}/history",
+    params(("id" = String, Path, description = "Short url of the redirection"), LookupParams),
+    responses(
+        (status = 200, description = "Destinations of the redirection", body = HistoryResponse),
+        (status = 404, description = "Unknown redirection", body = ErrorResponse),
+    )
+)]
+pub async fn list(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    lookup: web::Query<LookupParams>,
+) -> Result<impl Responder, Error> {
+    let conn = &data.conn;
+    let found = find_redirection(conn, id.into_inner(), &lookup).await?;
+
+    let versions = Query::find_redirection_history(conn, found.id)
+        .await
+        .map_err(ApiError::from)?;
+
+    Ok(Json(HistoryResponse {
+        id: found.id,
+        short_url: found.short_url,
+        versions,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/redirections/{id}/history/{version}/rollback",
+    params(
+        ("id" = String, Path, description = "Short url of the redirection"),
+        ("version" = i32, Path, description = "Version whose destination is brought back"),
+        LookupParams
+    ),
+    responses(
+        (status = 200, description = "Redirection rolled back, as a new version", body = Redirection),
+        (status = 400, description = "The destination of the version is no longer allowed", body = ErrorResponse),
+        (status = 404, description = "Unknown redirection or version", body = ErrorResponse),
+    )
+)]
+pub async fn rollback(
+    data: web::Data<AppState>,
+    cache: web::Data<AppCache>,
+    actor: RequestActor,
+    path: web::Path<(String, i32)>,
+    lookup: web::Query<LookupParams>,
+) -> Result<impl Responder, Error> {
+    let conn = &data.conn;
+    let (id, version) = path.into_inner();
+    let found = find_redirection(conn, id, &lookup).await?;
+
+    // the policies may have changed since the version was recorded
+    let previous = Query::find_redirection_version(conn, found.id, version)
+        .await
+        .map_err(ApiError::from)?
+        .ok_or_else(|| ApiError::NotFound("Version not found".to_owned()))?;
+    validate_url(&previous.long_url, &data.policies)?;
+
+    let rolled_back =
+        Mutation::rollback_redirection(conn, cache.cache.as_ref(), &actor.0, found.id, version)
+            .await
+            .map_err(ApiError::from)?;
+
+    Ok(Json(rolled_back))
+}
@@ -0,0 +1,275 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::time::{Duration as StdDuration, Instant};
+use std::{env, io, process};
+
+use actix_rt::time::{sleep, timeout};
+use futures_util::future::{select, Either};
+use futures_util::pin_mut;
+use rus_core::chrono::Utc;
+use rus_core::errors::RusError;
+use rus_core::redis::aio::ConnectionManager;
+use rus_core::redis::{self, RedisError, RedisResult, Script};
+use rus_core::sea_orm::{
+    ConnectionTrait, DatabaseConnection, DbBackend, DbErr, Statement, TransactionTrait,
+};
+use tracing::warn;
+
+/// How long a redis lock is held without being renewed, in case its holder dies
+const LEASE: StdDuration = StdDuration::from_secs(10);
+/// The replicas' clocks are not perfectly in sync, so a lock is held a little while after a
+/// short run, to not let a late replica run the job again for the same tick
+const MAX_CLOCK_SKEW: StdDuration = StdDuration::from_secs(5);
+
+/// Extends the lease, as long as it still belongs to this replica
+const RENEW_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// Releases the lock, or keeps it for the remaining milliseconds when some are given
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    if tonumber(ARGV[2]) > 0 then
+        return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+    end
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Why a job didn't run to completion under its lock
+#[derive(Debug)]
+pub enum LockError {
+    /// The lock couldn't be acquired, the job didn't run
+    Unavailable(RusError),
+    /// The lease expired or was taken over while the job was running, which was cancelled
+    Lost,
+}
+
+impl From<RusError> for LockError {
+    fn from(err: RusError) -> Self {
+        LockError::Unavailable(err)
+    }
+}
+
+impl From<DbErr> for LockError {
+    fn from(err: DbErr) -> Self {
+        LockError::Unavailable(err.into())
+    }
+}
+
+/// Makes sure a job runs on a single replica for each tick of its schedule.
+/// The ticks are multiples of the interval since the Unix epoch, so the replicas compete for
+/// the same tick
+#[derive(Default)]
+pub enum JobLock {
+    /// Every replica runs the jobs, for single instance deployments
+    #[default]
+    Local,
+    /// Lock with a lease, renewed while the job runs
+    Redis {
+        connection: ConnectionManager,
+        timeout: StdDuration,
+        /// Identifies the replica holding the lock
+        token: String,
+    },
+    /// Transaction level advisory lock, held by an open transaction while the job runs
+    Postgres(DatabaseConnection),
+}
+
+impl Debug for JobLock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobLock::Local => write!(f, "Local"),
+            JobLock::Redis { token, .. } => f.debug_struct("Redis").field("token", token).finish(),
+            JobLock::Postgres(_) => write!(f, "Postgres"),
+        }
+    }
+}
+
+/// Advisory locks are identified by a number, the hash must be the same on every replica
+fn advisory_key(name: &str) -> i64 {
+    // FNV-1a
+    let hash = format!("rus:jobs:{}", name)
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    hash as i64
+}
+
+/// Bounds the duration of a redis command, so a slow server can't hold the job forever
+async fn with_timeout<T>(
+    limit: StdDuration,
+    command: impl Future<Output = RedisResult<T>>,
+) -> Result<T, RusError> {
+    let result = timeout(limit, command).await.unwrap_or_else(|_| {
+        Err(RedisError::from(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Redis command timed out",
+        )))
+    });
+    Ok(result?)
+}
+
+/// Renews the lease while the job runs, returns once it is lost: when another replica took the
+/// lock over, or when the renewals kept failing until the lease would expire before the next one
+async fn keep_lease(
+    mut conn: ConnectionManager,
+    limit: StdDuration,
+    key: String,
+    token: String,
+    acquired_at: Instant,
+) {
+    let script = Script::new(RENEW_SCRIPT);
+    let mut expires_at = acquired_at + LEASE;
+    loop {
+        sleep(LEASE / 3).await;
+        let sent_at = Instant::now();
+        let renewed: Result<i64, RusError> = with_timeout(
+            limit,
+            script
+                .key(&key)
+                .arg(&token)
+                .arg(LEASE.as_millis() as u64)
+                .invoke_async(&mut conn),
+        )
+        .await;
+        match renewed {
+            Ok(1) => expires_at = sent_at + LEASE,
+            Ok(_) => {
+                warn!("Lost the lock {}, cancelling the job", key);
+                return;
+            }
+            Err(err) if Instant::now() + LEASE / 3 + limit >= expires_at => {
+                warn!(
+                    "Failed to renew the lock {} before its lease ends ({}), cancelling the job",
+                    key,
+                    err.name()
+                );
+                return;
+            }
+            Err(err) => warn!("Failed to renew the lock {} : {}", key, err.name()),
+        }
+    }
+}
+
+impl JobLock {
+    pub async fn redis(client: redis::Client, timeout: StdDuration) -> Result<JobLock, RusError> {
+        let connection = with_timeout(timeout, ConnectionManager::new(client)).await?;
+        Ok(JobLock::Redis {
+            connection,
+            timeout,
+            token: format!(
+                "{}-{}-{}",
+                env::var("HOSTNAME").unwrap_or_default(),
+                process::id(),
+                Utc::now().timestamp_millis()
+            ),
+        })
+    }
+
+    /// Hold time left once a job is done, so that the lock covers the whole tick
+    fn remaining_hold(interval: StdDuration, started: Instant) -> StdDuration {
+        (interval / 2)
+            .min(MAX_CLOCK_SKEW)
+            .saturating_sub(started.elapsed())
+    }
+
+    /// Runs the task if this replica got the lock for the current tick, returns `None` when
+    /// another replica holds it
+    pub async fn run<T>(
+        &self,
+        name: &str,
+        interval: StdDuration,
+        task: impl Future<Output = T>,
+    ) -> Result<Option<T>, LockError> {
+        match self {
+            JobLock::Local => Ok(Some(task.await)),
+            JobLock::Redis {
+                connection,
+                timeout,
+                token,
+            } => {
+                let key = format!("rus:jobs:{}", name);
+                let started = Instant::now();
+                let mut conn = connection.clone();
+                let acquired: Option<String> = with_timeout(
+                    *timeout,
+                    redis::cmd("SET")
+                        .arg(&key)
+                        .arg(token)
+                        .arg("NX")
+                        .arg("PX")
+                        .arg(LEASE.as_millis() as u64)
+                        .query_async(&mut conn),
+                )
+                .await?;
+                if acquired.is_none() {
+                    return Ok(None);
+                }
+
+                let renewal = actix_rt::spawn(keep_lease(
+                    conn.clone(),
+                    *timeout,
+                    key.clone(),
+                    token.clone(),
+                    started,
+                ));
+                pin_mut!(task);
+                let result = match select(task, renewal).await {
+                    Either::Left((result, renewal)) => {
+                        renewal.abort();
+                        result
+                    }
+                    // the task is dropped, so it stops at its next await
+                    Either::Right(_) => return Err(LockError::Lost),
+                };
+
+                let released: Result<i64, RusError> = with_timeout(
+                    *timeout,
+                    Script::new(RELEASE_SCRIPT)
+                        .key(&key)
+                        .arg(token)
+                        .arg(Self::remaining_hold(interval, started).as_millis() as u64)
+                        .invoke_async(&mut conn),
+                )
+                .await;
+                if let Err(err) = released {
+                    // the lease expires by itself
+                    warn!("Failed to release the lock {} : {}", key, err.name());
+                }
+                Ok(Some(result))
+            }
+            JobLock::Postgres(conn) => {
+                let started = Instant::now();
+                let txn = conn.begin().await?;
+                let locked = txn
+                    .query_one(Statement::from_sql_and_values(
+                        DbBackend::Postgres,
+                        "SELECT pg_try_advisory_xact_lock($1) AS locked",
+                        vec![advisory_key(name).into()],
+                    ))
+                    .await?
+                    .map(|row| row.try_get::<bool>("", "locked"))
+                    .transpose()?
+                    .unwrap_or(false);
+                if !locked {
+                    txn.rollback().await?;
+                    return Ok(None);
+                }
+
+                let result = task.await;
+                sleep(Self::remaining_hold(interval, started)).await;
+                // ending the transaction releases the lock, even when it fails
+                if let Err(err) = txn.commit().await {
+                    warn!("Failed to release the lock of job {} : {}", name, err);
+                }
+                Ok(Some(result))
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration as StdDuration, Instant, SystemTime, UNIX_EPOCH};
+
+pub use self::lock::{JobLock, LockError};
+use crate::conf::JobConfig;
+use crate::metrics::{record_job_run, record_job_skipped};
+use crate::AppState;
+use actix_rt::time::{sleep, timeout};
+use rus_core::chrono::{Duration, NaiveDateTime, Utc};
+use rus_core::errors::RusError;
+use rus_core::{Actor, Cache, Mutation, Query};
+use serde::Serialize;
+use tracing::{debug, info, instrument, warn};
+use utoipa::ToSchema;
+
+mod lock;
+
+pub const EXPIRED_LINKS_JOB: &str = "expired_links";
+pub const CACHE_PURGE_JOB: &str = "cache_purge";
+pub const TRASH_PURGE_JOB: &str = "trash_purge";
+
+/// Error of the runs cancelled after losing their lock
+const LOCK_LOST: &str = "lock_lost";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Success,
+    Failure,
+    Timeout,
+}
+
+impl JobOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobOutcome::Success => "success",
+            JobOutcome::Failure => "failure",
+            JobOutcome::Timeout => "timeout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobRun {
+    started_at: NaiveDateTime,
+    duration_ms: u64,
+    outcome: JobOutcome,
+    /// Rows or cache entries the run removed, when it succeeded
+    affected_rows: Option<u64>,
+    /// Code of the error of a failed run, as in the API error responses, or `lock_lost` when
+    /// the run was cancelled because another replica could take its lock over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    name: &'static str,
+    enabled: bool,
+    /// Whether each run happens on a single replica, instead of all of them
+    exclusive: bool,
+    interval_secs: u32,
+    timeout_secs: u64,
+    running: bool,
+    runs: u64,
+    failures: u64,
+    /// Ticks during which another replica ran the job, or the lock couldn't be acquired
+    skipped: u64,
+    last_run: Option<JobRun>,
+}
+
+/// Time left until the next multiple of the interval since the Unix epoch, so that the replicas
+/// wake up for the same ticks whatever time they started at
+fn until_next_tick(interval: StdDuration, now: SystemTime) -> StdDuration {
+    let interval_ms = interval.as_millis().max(1);
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    StdDuration::from_millis((interval_ms - since_epoch.as_millis() % interval_ms) as u64)
+}
+
+/// Maintenance jobs of the instance, along with the outcome of their last run
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    jobs: Vec<Mutex<JobStatus>>,
+    /// Shared with the other replicas, to run the exclusive jobs only once per tick
+    lock: JobLock,
+}
+
+impl JobRegistry {
+    pub fn new(lock: JobLock) -> JobRegistry {
+        JobRegistry { jobs: vec![], lock }
+    }
+
+    /// Registers a job running on every replica, e.g. to maintain their local state
+    pub fn register(&mut self, name: &'static str, conf: &JobConfig) {
+        self.add(name, conf, false)
+    }
+
+    /// Registers a job running on a single replica for each tick
+    pub fn register_exclusive(&mut self, name: &'static str, conf: &JobConfig) {
+        self.add(name, conf, true)
+    }
+
+    fn add(&mut self, name: &'static str, conf: &JobConfig, exclusive: bool) {
+        self.jobs.push(Mutex::new(JobStatus {
+            name,
+            enabled: conf.enabled,
+            exclusive,
+            interval_secs: conf.interval_secs,
+            timeout_secs: conf.timeout_secs,
+            running: false,
+            runs: 0,
+            failures: 0,
+            skipped: 0,
+            last_run: None,
+        }));
+    }
+
+    /// The statuses are only held for a few instructions, a panic can't leave them inconsistent
+    fn status(&self, name: &str) -> Option<MutexGuard<'_, JobStatus>> {
+        self.jobs
+            .iter()
+            .map(|job| job.lock().unwrap_or_else(PoisonError::into_inner))
+            .find(|status| status.name == name)
+    }
+
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.jobs
+            .iter()
+            .map(|job| job.lock().unwrap_or_else(PoisonError::into_inner).clone())
+            .collect()
+    }
+
+    /// Runs the job every `interval_secs` until the server stops, unless it is disabled
+    pub async fn schedule<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: Future<Output = Result<u64, RusError>> + Send,
+    {
+        let (enabled, exclusive, interval_secs, timeout_secs) = match self.status(name) {
+            Some(status) => (
+                status.enabled,
+                status.exclusive,
+                status.interval_secs,
+                status.timeout_secs,
+            ),
+            None => {
+                warn!("Job {} is not registered", name);
+                return;
+            }
+        };
+        if !enabled {
+            info!("Job {} is disabled", name);
+            return;
+        }
+        let interval = StdDuration::from_secs(interval_secs as u64);
+        let limit = StdDuration::from_secs(timeout_secs);
+        loop {
+            sleep(until_next_tick(interval, SystemTime::now())).await;
+            self.run(name, exclusive.then_some(interval), limit, task())
+                .await;
+        }
+    }
+
+    /// Exclusive jobs are given their interval, to hold the lock for the whole tick
+    #[instrument(name = "job", skip(self, exclusive, limit, task))]
+    async fn run<Fut>(
+        &self,
+        name: &'static str,
+        exclusive: Option<StdDuration>,
+        limit: StdDuration,
+        task: Fut,
+    ) where
+        Fut: Future<Output = Result<u64, RusError>> + Send,
+    {
+        let started_at = Utc::now().naive_utc();
+        let locked_at = Instant::now();
+        let running = async {
+            if let Some(mut status) = self.status(name) {
+                status.running = true;
+            }
+            let start = Instant::now();
+            let result = timeout(limit, task).await;
+            (result, start.elapsed())
+        };
+        let ran = match exclusive {
+            Some(interval) => self.lock.run(name, interval, running).await,
+            None => Ok(Some(running.await)),
+        };
+
+        let (outcome, duration, affected_rows, error) = match ran {
+            Ok(None) => {
+                debug!("Job {} ran on another replica", name);
+                record_job_skipped(name);
+                if let Some(mut status) = self.status(name) {
+                    status.skipped += 1;
+                }
+                return;
+            }
+            // the other replicas most likely can't lock it either, one failure would be recorded
+            // by each of them
+            Err(LockError::Unavailable(err)) => {
+                warn!(
+                    "Failed to lock job {}, skipping this tick : {}",
+                    name,
+                    err.name()
+                );
+                record_job_skipped(name);
+                if let Some(mut status) = self.status(name) {
+                    status.skipped += 1;
+                }
+                return;
+            }
+            Err(LockError::Lost) => (
+                JobOutcome::Failure,
+                locked_at.elapsed(),
+                None,
+                Some(LOCK_LOST),
+            ),
+            Ok(Some((Ok(Ok(affected)), duration))) => {
+                (JobOutcome::Success, duration, Some(affected), None)
+            }
+            Ok(Some((Ok(Err(err)), duration))) => {
+                warn!("Job {} failed : {}", name, err.name());
+                (JobOutcome::Failure, duration, None, Some(err.code()))
+            }
+            Ok(Some((Err(_), duration))) => {
+                warn!("Job {} timed out after {:?}", name, limit);
+                (JobOutcome::Timeout, duration, None, None)
+            }
+        };
+        record_job_run(name, outcome.as_str(), duration, affected_rows);
+
+        if let Some(mut status) = self.status(name) {
+            status.running = false;
+            status.runs += 1;
+            if outcome != JobOutcome::Success {
+                status.failures += 1;
+            }
+            status.last_run = Some(JobRun {
+                started_at,
+                duration_ms: duration.as_millis() as u64,
+                outcome,
+                affected_rows,
+                error,
+            });
+        }
+    }
+}
+
+pub async fn remove_expired_redirections(
+    app_state: AppState,
+    cache: Arc<dyn Cache>,
+    registry: Arc<JobRegistry>,
+) {
+    let conn = &app_state.conn;
+    registry
+        .schedule(EXPIRED_LINKS_JOB, || async {
+            let removed = Mutation::remove_expired_redirections(conn, cache.as_ref()).await?;
+            if !removed.is_empty() {
+                info!("Moved {} expired redirections to the trash", removed.len())
+            }
+            Ok(removed.len() as u64)
+        })
+        .await;
+}
+
+/// Preloads the most recently accessed links, so a fresh instance doesn't send every redirection to the database
+pub async fn warm_up_cache(app_state: AppState, cache: Arc<dyn Cache>, count: u64) {
+    let redirections = match Query::find_recently_accessed(&app_state.conn, count).await {
+        Ok(redirections) => redirections,
+        Err(err) => {
+            warn!("Failed to load redirections to warm up the cache : {}", err);
+            return;
+        }
+    };
+
+    let mut loaded = 0;
+    for redirection in redirections {
+        let added = cache
+            .add_entry(
+                redirection.short_url,
+                redirection.long_url,
+                redirection.expiration_date.unwrap_or(NaiveDateTime::MAX),
+            )
+            .await;
+        match added {
+            Ok(()) => loaded += 1,
+            Err(err) => warn!("Failed to warm up the cache : {}", err.name()),
+        }
+    }
+    info!("Warmed up the cache with {} redirections", loaded);
+}
+
+pub async fn purge_expired_cache_entries(cache: Arc<dyn Cache>, registry: Arc<JobRegistry>) {
+    registry
+        .schedule(CACHE_PURGE_JOB, || async {
+            let purged = cache.purge_expired().await;
+            if purged > 0 {
+                debug!("Purged {} expired entries from cache", purged)
+            }
+            Ok(purged as u64)
+        })
+        .await;
+}
+
+pub async fn purge_trash(app_state: AppState, retention: Duration, registry: Arc<JobRegistry>) {
+    let conn = &app_state.conn;
+    registry
+        .schedule(TRASH_PURGE_JOB, || async {
+            let deleted_before = Utc::now().naive_utc() - retention;
+            let purged = Mutation::purge_trash(conn, &Actor::System, deleted_before).await?;
+            if purged > 0 {
+                info!("Purged {} redirections from the trash", purged)
+            }
+            Ok(purged)
+        })
+        .await;
+}
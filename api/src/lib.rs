@@ -1,120 +1,346 @@
 extern crate core;
 
-use std::collections::HashMap;
 use std::env;
 use std::fmt::Debug;
-use std::sync::Mutex;
+use std::num::NonZeroUsize;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
 
 use actix_files::Files as Fs;
-use actix_web::{middleware, web, App, HttpServer};
+use actix_web::web::ServiceConfig;
+use actix_web::{web, App, HttpServer};
+use clap::Parser;
 use listenfd::ListenFd;
-use log::{error, info, warn, LevelFilter};
+use log::LevelFilter;
 use serde::Deserialize;
+use tracing::{error, info, warn};
+use tracing_actix_web::TracingLogger;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::conf::RusConf;
-use crate::jobs::remove_expired_redirections;
+use crate::cli::{Cli, Command, ConfigCommand};
+use crate::conf::{database_backend, CacheConfig, Config, PoliciesConfig};
+use crate::jobs::{
+    purge_expired_cache_entries, purge_trash, remove_expired_redirections, warm_up_cache, JobLock,
+    JobRegistry, CACHE_PURGE_JOB, EXPIRED_LINKS_JOB, TRASH_PURGE_JOB,
+};
 use crate::routes::init;
+use crate::telemetry::RequestSpan;
 use migration::{Migrator, MigratorTrait};
-use rus_core::chrono::Duration;
-use rus_core::sea_orm::ConnectOptions;
+use rus_core::chrono::{Duration, NaiveDateTime};
+use rus_core::sea_orm::{ConnectOptions, ConnectionTrait, DbBackend};
 use rus_core::{
+    errors::RusError,
     redis,
     sea_orm::{Database, DatabaseConnection},
-    Cache,
+    AuditAction, Cache, InMemoryCache, LinkState, RedisCache, SortField, SortOrder, TieredCache,
 };
 
+mod admin;
 mod api;
-mod conf;
+mod audit;
+mod cli;
+pub mod conf;
 mod errors;
-mod jobs;
+mod health;
+mod history;
+pub mod jobs;
+mod metrics;
+mod openapi;
+mod payload;
 mod routes;
+mod telemetry;
+mod trash;
 
 const DEFAULT_REDIRECTIONS_PER_PAGE: u64 = 100;
-const DEFAULT_WEB_HOST: &str = "0.0.0.0";
-const DEFAULT_WEB_PORT: &str = "8000";
-const DEFAULT_LINK_LIFETIME: i64 = 90;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     conn: DatabaseConnection,
     link_lifetime: Duration,
+    /// Bearer token of the administration endpoints, disabled when missing
+    admin_token: Option<String>,
+    policies: PoliciesConfig,
+    jobs: Arc<JobRegistry>,
+}
+
+impl AppState {
+    pub fn new(conn: DatabaseConnection, link_lifetime: Duration) -> AppState {
+        AppState {
+            conn,
+            link_lifetime,
+            admin_token: None,
+            policies: PoliciesConfig::default(),
+            jobs: Arc::new(JobRegistry::default()),
+        }
+    }
+
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> AppState {
+        self.admin_token = admin_token;
+        self
+    }
+
+    pub fn with_policies(mut self, policies: PoliciesConfig) -> AppState {
+        self.policies = policies;
+        self
+    }
+
+    pub fn with_jobs(mut self, jobs: Arc<JobRegistry>) -> AppState {
+        self.jobs = jobs;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct AppCache {
-    cache: Cache,
+    cache: Arc<dyn Cache>,
+    /// How long unknown short urls are remembered as missing
+    negative_ttl: Duration,
+}
+
+impl AppCache {
+    pub fn new(cache: Arc<dyn Cache>, negative_ttl: Duration) -> AppCache {
+        AppCache {
+            cache,
+            negative_ttl,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Params {
+    /// Page to fetch, starting at 1
     page: Option<u64>,
     redirections_per_page: Option<u64>,
+    /// Cursor returned as `next_cursor` by a previous listing, switches to keyset pagination
+    after: Option<String>,
+    sort: Option<SortField>,
+    order: Option<SortOrder>,
+    /// Host of the destination url
+    domain: Option<String>,
+    created_after: Option<NaiveDateTime>,
+    created_before: Option<NaiveDateTime>,
+    state: Option<LinkState>,
+    /// Ip address of the creator of the link
+    ip: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LookupParams {
+    /// Address the redirection by its numeric id instead of its short url
+    by_id: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrashParams {
+    /// Page to fetch, starting at 1
+    page: Option<u64>,
+    redirections_per_page: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditParams {
+    /// Page to fetch, starting at 1
+    page: Option<u64>,
+    events_per_page: Option<u64>,
+    short_url: Option<String>,
+    action: Option<AuditAction>,
+    /// Name of the API key or ip address
+    actor: Option<String>,
+    /// Only the events recorded from this date
+    after: Option<NaiveDateTime>,
+    /// Only the events recorded before this date
+    before: Option<NaiveDateTime>,
+}
+
+/// Accepted both as JSON and as an url-encoded form
+#[derive(Deserialize, ToSchema)]
 pub struct CreateForm {
     long_url: String,
+    title: Option<String>,
+    notes: Option<String>,
 }
 
-fn create_cache() -> Cache {
-    let redis_url = RusConf::RedisUrl.get();
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    /// Search terms
+    q: String,
+    page: Option<u64>,
+    redirections_per_page: Option<u64>,
+}
 
-    if let Some(url) = redis_url {
-        if let Ok(client) = redis::Client::open(url) {
-            info!("Using redis as cache");
-            Cache::Redis(client)
-        } else {
-            warn!("Failed to open redis connection, fallback to in-memory cache");
-            Cache::InMemory(HashMap::new())
+/// The configuration is validated beforehand, so the cache can hold at least one entry
+fn create_in_memory_cache(conf: &CacheConfig) -> InMemoryCache {
+    let max_entries =
+        NonZeroUsize::new(conf.max_entries).expect("cache.max_entries must be at least 1");
+    InMemoryCache::new(max_entries, conf.max_bytes)
+}
+
+/// A local tier is put in front of redis unless its lifetime is set to 0.
+/// Without it, an in-memory cache takes over while redis is unavailable
+async fn create_redis_cache(
+    client: redis::Client,
+    conf: &CacheConfig,
+) -> Result<Arc<dyn Cache>, RusError> {
+    let timeout = StdDuration::from_millis(conf.redis_timeout_ms);
+    if conf.local_ttl_secs > 0 {
+        info!("Using redis as cache, with a local tier");
+        let local = create_in_memory_cache(conf);
+        let local_ttl = Duration::seconds(conf.local_ttl_secs);
+        let cache = TieredCache::connect(client, timeout, local, local_ttl).await?;
+        Ok(Arc::new(cache))
+    } else {
+        info!("Using redis as cache");
+        let cache = RedisCache::connect(client, timeout)
+            .await?
+            .with_fallback(create_in_memory_cache(conf));
+        Ok(Arc::new(cache))
+    }
+}
+
+async fn create_cache(conf: &CacheConfig) -> Arc<dyn Cache> {
+    if let Some(url) = &conf.redis_url {
+        let redis_cache = match redis::Client::open(url.as_str()) {
+            Ok(client) => create_redis_cache(client, conf).await,
+            Err(err) => Err(err.into()),
+        };
+        match redis_cache {
+            Ok(redis_cache) => redis_cache,
+            Err(err) => {
+                warn!(
+                    "Failed to open redis connection ({}), fallback to in-memory cache",
+                    err.name()
+                );
+                Arc::new(create_in_memory_cache(conf))
+            }
         }
     } else {
         info!("No redis url found, using in-memory cache");
-        Cache::InMemory(HashMap::new())
+        Arc::new(create_in_memory_cache(conf))
+    }
+}
+
+/// The replicas share the redis server, or a postgres database, to run each maintenance job
+/// only once. Otherwise the instance is expected to be the only one
+async fn create_job_lock(config: &Config, conn: &DatabaseConnection) -> JobLock {
+    if let Some(url) = &config.cache.redis_url {
+        let timeout = StdDuration::from_millis(config.cache.redis_timeout_ms);
+        let lock = match redis::Client::open(url.as_str()) {
+            Ok(client) => JobLock::redis(client, timeout).await,
+            Err(err) => Err(err.into()),
+        };
+        match lock {
+            Ok(lock) => {
+                info!("Maintenance jobs are locked through redis");
+                return lock;
+            }
+            Err(err) => warn!("Failed to lock the jobs through redis ({})", err.name()),
+        }
+    }
+    if conn.get_database_backend() == DbBackend::Postgres {
+        info!("Maintenance jobs are locked through postgres");
+        JobLock::Postgres(conn.clone())
+    } else {
+        warn!("Maintenance jobs run on every instance, no lock is available");
+        JobLock::Local
+    }
+}
+
+/// Registers the routes along with the state they share, the cache must be shared by all the workers
+pub fn configure(
+    state: AppState,
+    cache: web::Data<AppCache>,
+) -> impl Fn(&mut ServiceConfig) + Clone {
+    move |cfg| {
+        cfg.app_data(web::Data::new(state.clone()))
+            .app_data(cache.clone())
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| errors::ApiError::from(err).into()),
+            )
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _| errors::ApiError::from(err).into()),
+            )
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|err, _| errors::ApiError::from(err).into()),
+            );
+        init(cfg);
     }
 }
 
 #[actix_web::main]
-async fn start() -> std::io::Result<()> {
-    env::set_var("RUST_LOG", "info");
-    tracing_subscriber::fmt::init();
+async fn start(config: Config) -> std::io::Result<()> {
+    telemetry::init(&config.tracing);
 
-    // get env vars
-    dotenvy::dotenv().ok();
-    let db_url = RusConf::DatabaseUrl
-        .get()
-        .expect("Must set RUS_DATABASE_URL env variable");
-    let host = RusConf::WebHost.get_or(DEFAULT_WEB_HOST.to_owned());
-    let port = RusConf::WebPort.get_or(DEFAULT_WEB_PORT.to_owned());
-    let server_url = format!("{}:{}", host, port);
+    let db_url = config
+        .database
+        .url
+        .to_owned()
+        .expect("The database url is validated beforehand");
+    if let Ok(backend) = database_backend(&db_url) {
+        info!("Using {:?} database", backend);
+    }
+    let server_url = format!("{}:{}", config.server.host, config.server.port);
     let mut connect_options = ConnectOptions::new(db_url);
-    connect_options.sqlx_logging_level(LevelFilter::Debug);
+    connect_options
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .connect_timeout(StdDuration::from_secs(config.database.connect_timeout_secs))
+        .idle_timeout(StdDuration::from_secs(config.database.idle_timeout_secs))
+        .sqlx_logging_level(LevelFilter::Debug);
 
-    let conn = Database::connect(connect_options)
+    let mut conn = Database::connect(connect_options)
         .await
         .expect("Failed to connect to the database");
+    conn.set_metric_callback(metrics::observe_query);
 
     Migrator::up(&conn, None).await.unwrap();
 
-    let link_lifetime = Duration::days(RusConf::LinkDaysLifeTime.get_i64_or(DEFAULT_LINK_LIFETIME));
-    let state = AppState {
-        conn,
-        link_lifetime,
-    };
-    let cache = AppCache {
-        cache: create_cache(),
-    };
+    let mut registry = JobRegistry::new(create_job_lock(&config, &conn).await);
+    registry.register_exclusive(EXPIRED_LINKS_JOB, &config.jobs.expired_links);
+    registry.register(CACHE_PURGE_JOB, &config.jobs.cache_purge);
+    registry.register_exclusive(TRASH_PURGE_JOB, &config.jobs.trash_purge);
+    let registry = Arc::new(registry);
+
+    let link_lifetime = Duration::days(config.links.lifetime_days);
+    let state = AppState::new(conn, link_lifetime)
+        .with_admin_token(config.admin.token.to_owned())
+        .with_policies(config.policies.to_owned())
+        .with_jobs(registry.clone());
+    // shared by all the workers
+    let cache = web::Data::new(AppCache::new(
+        create_cache(&config.cache).await,
+        Duration::seconds(config.cache.negative_ttl_secs),
+    ));
     let jobs_state = state.clone();
+    let jobs_cache = cache.cache.clone();
+    let purge_cache = cache.cache.clone();
+    let warmup_state = state.clone();
+    let warmup_cache = cache.cache.clone();
+    let purge_registry = registry.clone();
+    let trash_state = state.clone();
+    let trash_registry = registry.clone();
+    let trash_retention = Duration::days(config.links.trash_retention_days);
+    let warmup_size = config.cache.warmup_size;
 
     // create server and try to serve over socket if possible
     let mut listenfd = ListenFd::from_env();
     let mut server = HttpServer::new(move || {
         App::new()
             .service(Fs::new("/static", "./api/static"))
-            .app_data(web::Data::new(state.clone()))
-            .app_data(web::Data::new(Mutex::new(cache.clone())))
-            .wrap(middleware::Logger::default()) // enable logger
-            .configure(init)
+            .wrap(TracingLogger::<RequestSpan>::new()) // a span and a log line per request
+            .configure(configure(state.clone(), cache.clone()))
     });
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
 
     server = match listenfd.take_tcp_listener(0)? {
         Some(listener) => server.listen(listener)?,
@@ -122,19 +348,54 @@ async fn start() -> std::io::Result<()> {
     };
 
     actix_rt::spawn(async move {
-        remove_expired_redirections(jobs_state).await;
+        remove_expired_redirections(jobs_state, jobs_cache, registry).await;
+    });
+    actix_rt::spawn(async move {
+        purge_expired_cache_entries(purge_cache, purge_registry).await;
+    });
+    actix_rt::spawn(async move {
+        purge_trash(trash_state, trash_retention, trash_registry).await;
     });
+    if warmup_size > 0 {
+        actix_rt::spawn(async move {
+            warm_up_cache(warmup_state, warmup_cache, warmup_size).await;
+        });
+    }
 
     info!("Starting server at {}", server_url);
-    server.run().await?;
+    let served = server.run().await;
+    telemetry::shutdown().await;
+    served
+}
 
-    Ok(())
+/// Configuration from the file, the environment and the command line, in that order
+fn load_config(cli: &Cli) -> Result<Config, conf::ConfigError> {
+    let mut config = Config::load(cli.config.as_deref(), |name| env::var(name).ok())?;
+    cli.apply(&mut config);
+    config.validate()?;
+    Ok(config)
 }
 
 pub fn main() {
-    let result = start();
+    dotenvy::dotenv().ok();
+    let cli = Cli::parse();
 
-    if let Some(err) = result.err() {
-        error!("Error: {}", err)
+    let config = match load_config(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    match cli.command {
+        Some(Command::Config {
+            command: ConfigCommand::Check,
+        }) => print!("{}", config.redacted().to_toml()),
+        None => {
+            if let Err(err) = start(config) {
+                error!("Error: {}", err)
+            }
+        }
     }
 }
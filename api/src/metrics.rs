@@ -0,0 +1,175 @@
+use std::future::Future;
+use std::time::{Duration as StdDuration, Instant};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{web, Error, HttpResponse, Responder};
+use lazy_static::lazy_static;
+use rus_core::prometheus::{
+    self, register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder,
+    HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+use rus_core::sea_orm::metric::Info;
+use rus_core::Query;
+use tracing::warn;
+
+use crate::AppState;
+
+lazy_static! {
+    static ref REDIRECTS: IntCounterVec = register_int_counter_vec!(
+        "rus_redirects_total",
+        "Redirections served, by outcome",
+        &["outcome"]
+    )
+    .expect("The metrics are registered once");
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "rus_http_request_duration_seconds",
+        "Duration of the handling of the requests, by route",
+        &["method", "route", "status"]
+    )
+    .expect("The metrics are registered once");
+    static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "rus_db_query_duration_seconds",
+        "Duration of the database queries, by kind of statement",
+        &["statement", "failed"],
+        vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .expect("The metrics are registered once");
+    static ref JOB_RUNS: IntCounterVec = register_int_counter_vec!(
+        "rus_job_runs_total",
+        "Runs of the maintenance jobs, by outcome",
+        &["job", "outcome"]
+    )
+    .expect("The metrics are registered once");
+    static ref JOB_DURATION: HistogramVec = register_histogram_vec!(
+        "rus_job_duration_seconds",
+        "Duration of the runs of the maintenance jobs",
+        &["job"]
+    )
+    .expect("The metrics are registered once");
+    static ref JOB_AFFECTED_ROWS: IntCounterVec = register_int_counter_vec!(
+        "rus_job_affected_rows_total",
+        "Rows or cache entries removed by the maintenance jobs",
+        &["job"]
+    )
+    .expect("The metrics are registered once");
+    static ref LIVE_LINKS: IntGauge = register_int_gauge!(
+        "rus_live_links",
+        "Redirections currently served, neither trashed nor expired"
+    )
+    .expect("The metrics are registered once");
+}
+
+/// How a short url was resolved
+#[derive(Debug, Clone, Copy)]
+pub enum RedirectOutcome {
+    /// Served from the cache
+    Hit,
+    /// Served from the database
+    Miss,
+    NotFound,
+    Expired,
+}
+
+impl RedirectOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RedirectOutcome::Hit => "hit",
+            RedirectOutcome::Miss => "miss",
+            RedirectOutcome::NotFound => "not_found",
+            RedirectOutcome::Expired => "expired",
+        }
+    }
+}
+
+pub fn record_redirect(outcome: RedirectOutcome) {
+    REDIRECTS.with_label_values(&[outcome.as_str()]).inc();
+}
+
+pub fn record_job_run(job: &str, outcome: &str, duration: StdDuration, affected_rows: Option<u64>) {
+    JOB_RUNS.with_label_values(&[job, outcome]).inc();
+    JOB_DURATION
+        .with_label_values(&[job])
+        .observe(duration.as_secs_f64());
+    if let Some(affected_rows) = affected_rows {
+        JOB_AFFECTED_ROWS
+            .with_label_values(&[job])
+            .inc_by(affected_rows);
+    }
+}
+
+/// Ticks during which another replica ran the job, or the lock couldn't be acquired
+pub fn record_job_skipped(job: &str) {
+    JOB_RUNS.with_label_values(&[job, "skipped"]).inc();
+}
+
+/// Set as the metric callback of the database connection. The statements are labelled by their
+/// first keyword, their text would make too many series
+pub fn observe_query(info: &Info<'_>) {
+    let statement = info
+        .statement
+        .sql
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase();
+    let statement = match statement.as_str() {
+        "SELECT" | "INSERT" | "UPDATE" | "DELETE" => statement.as_str(),
+        _ => "OTHER",
+    };
+    DB_QUERY_DURATION
+        .with_label_values(&[statement, if info.failed { "true" } else { "false" }])
+        .observe(info.elapsed.as_secs_f64());
+}
+
+/// Measures the requests, labelled by the pattern of their route rather than their path, so
+/// that every short url doesn't get its own series
+pub fn observe_request<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let response = srv.call(req);
+    async move {
+        let response = response.await?;
+        let route = response
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_owned());
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[&method, &route, response.status().as_str()])
+            .observe(started.elapsed().as_secs_f64());
+        Ok(response)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Metrics in the prometheus text format", body = String, content_type = "text/plain"),
+    )
+)]
+pub async fn metrics(data: web::Data<AppState>) -> impl Responder {
+    // the other metrics are still worth scraping while the database is unavailable
+    match Query::count_live_redirections(&data.conn).await {
+        Ok(live_links) => LIVE_LINKS.set(live_links as i64),
+        Err(err) => warn!(
+            "Failed to count the live links, keeping the last count : {}",
+            err
+        ),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        warn!("Failed to encode the metrics : {}", err);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
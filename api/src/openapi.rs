@@ -0,0 +1,88 @@
+use actix_web::web::Json;
+use actix_web::Responder;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::errors::ErrorResponse;
+use crate::jobs::{JobOutcome, JobRun, JobStatus};
+use crate::CreateForm;
+use crate::{admin, api, audit, health, history, metrics, trash};
+use entity::audit_event::Model as AuditEvent;
+use entity::redirection::Model as Redirection;
+use entity::redirection_version::Model as RedirectionVersion;
+use rus_core::{AuditAction, CacheStats, LinkState, SortField, SortOrder};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Rus", description = "Rust Url Shortener"),
+    paths(
+        api::list,
+        api::search,
+        api::create,
+        api::get,
+        api::update,
+        api::delete,
+        history::list,
+        history::rollback,
+        trash::list,
+        trash::restore,
+        trash::purge,
+        trash::empty,
+        admin::cache_stats,
+        admin::flush_cache,
+        admin::purge_cache_entry,
+        admin::jobs,
+        audit::events,
+        health::healthz,
+        health::readyz,
+        metrics::metrics
+    ),
+    components(schemas(
+        Redirection,
+        CreateForm,
+        api::ListResponse,
+        api::CreateResponse,
+        api::DeletedResponse,
+        RedirectionVersion,
+        history::HistoryResponse,
+        trash::TrashResponse,
+        trash::EmptiedResponse,
+        ErrorResponse,
+        SortField,
+        SortOrder,
+        LinkState,
+        CacheStats,
+        admin::FlushedResponse,
+        JobStatus,
+        JobRun,
+        JobOutcome,
+        AuditEvent,
+        AuditAction,
+        audit::AuditResponse,
+        health::CheckStatus,
+        health::AliveResponse,
+        health::DependencyCheck,
+        health::MigrationsCheck,
+        health::ReadyResponse
+    )),
+    modifiers(&AdminToken)
+)]
+pub struct ApiDoc;
+
+/// Bearer token required by the administration endpoints
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+pub async fn spec() -> impl Responder {
+    Json(ApiDoc::openapi())
+}
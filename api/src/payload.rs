@@ -0,0 +1,45 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::dev;
+use actix_web::web::{JsonBody, UrlEncoded};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use serde::de::DeserializeOwned;
+
+use crate::errors::ApiError;
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Request body extractor accepting either a JSON or an url-encoded form payload,
+/// depending on the `Content-Type` header of the request.
+pub struct Payload<T>(pub T);
+
+impl<T> Payload<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+fn is_json(content_type: &str) -> bool {
+    content_type == JSON_CONTENT_TYPE || content_type.ends_with("+json")
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Payload<T> {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let content_type = req.content_type().to_lowercase();
+
+        if is_json(&content_type) {
+            let body = JsonBody::<T>::new(req, payload, None, false);
+            Box::pin(async move { body.await.map(Payload).map_err(ApiError::from) })
+        } else if content_type == FORM_CONTENT_TYPE {
+            let body = UrlEncoded::<T>::new(req, payload);
+            Box::pin(async move { body.await.map(Payload).map_err(ApiError::from) })
+        } else {
+            Box::pin(async move { Err(ApiError::UnsupportedMediaType(content_type)) })
+        }
+    }
+}
@@ -1,20 +1,49 @@
 use actix_web::web::{delete, get, post, put, route, scope, ServiceConfig};
 
-use crate::api;
+use crate::{admin, api, audit, health, history, metrics, openapi, trash};
 
 pub fn init(cfg: &mut ServiceConfig) {
     cfg.service(
         scope("")
+            .wrap_fn(metrics::observe_request)
             .service(scope("/").route("", get().to(api::home)))
             .service(
-                scope("/api/v1").service(
-                    scope("/redirections")
-                        .route("", get().to(api::list))
-                        .route("", post().to(api::create))
-                        .route("/{id}", delete().to(api::delete))
-                        .route("/{id}", put().to(api::update)),
-                ),
+                scope("/api/v1")
+                    .route("/openapi.json", get().to(openapi::spec))
+                    .route("/search", get().to(api::search))
+                    .service(
+                        scope("/redirections")
+                            .route("", get().to(api::list))
+                            .route("", post().to(api::create))
+                            .route("/{id}", get().to(api::get))
+                            .route("/{id}", delete().to(api::delete))
+                            .route("/{id}", put().to(api::update))
+                            .route("/{id}/history", get().to(history::list))
+                            .route(
+                                "/{id}/history/{version}/rollback",
+                                post().to(history::rollback),
+                            ),
+                    )
+                    .service(
+                        scope("/trash")
+                            .route("", get().to(trash::list))
+                            .route("", delete().to(trash::empty))
+                            .route("/{id}", delete().to(trash::purge))
+                            .route("/{id}/restore", post().to(trash::restore)),
+                    )
+                    .service(
+                        scope("/admin/cache")
+                            .route("", get().to(admin::cache_stats))
+                            .route("", delete().to(admin::flush_cache))
+                            .route("/{key}", delete().to(admin::purge_cache_entry)),
+                    )
+                    .route("/admin/jobs", get().to(admin::jobs))
+                    .route("/admin/audit", get().to(audit::events)),
             )
+            // before the redirections, which would otherwise match them
+            .route("/healthz", get().to(health::healthz))
+            .route("/readyz", get().to(health::readyz))
+            .route("/metrics", get().to(metrics::metrics))
             .route("/{id}", get().to(api::redirect)),
     )
     .default_service(route().to(api::home));
@@ -0,0 +1,118 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use tracing::{info, warn, Span};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::conf::TracingConfig;
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::trace::TraceError;
+    use opentelemetry::{global, runtime, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    use crate::conf::TracingConfig;
+
+    /// The spans are exported in batches by a task of the runtime it is called from
+    pub fn layer<S>(
+        conf: &TracingConfig,
+    ) -> Result<Option<OpenTelemetryLayer<S, trace::Tracer>>, TraceError>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = match &conf.otlp_endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+        // continues the traces of the callers sending a `traceparent` header
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer =
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", conf.service_name.to_owned()),
+                ])))
+                .install_batch(runtime::Tokio)?;
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    /// Exports the spans still in the batch. The provider blocks until they are sent by the
+    /// runtime, which must keep running meanwhile
+    pub async fn shutdown() {
+        let _ = actix_rt::task::spawn_blocking(global::shutdown_tracer_provider).await;
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+mod otlp {
+    use std::convert::Infallible;
+
+    use tracing_subscriber::layer::Identity;
+
+    use crate::conf::TracingConfig;
+
+    /// The validation of the configuration rejects an endpoint without the `otlp` feature
+    pub fn layer(_: &TracingConfig) -> Result<Option<Identity>, Infallible> {
+        Ok(None)
+    }
+
+    pub async fn shutdown() {}
+}
+
+/// Logs to the standard output, filtered by `RUST_LOG`, and exports the spans when a collector
+/// is configured. Must be called from within the runtime of the server
+pub fn init(conf: &TracingConfig) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (exporter, error) = match otlp::layer(conf) {
+        Ok(exporter) => (exporter, None),
+        Err(err) => (None, Some(err)),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(exporter)
+        .init();
+
+    match (error, &conf.otlp_endpoint) {
+        (Some(err), _) => warn!("Failed to export the traces : {}", err),
+        (None, Some(endpoint)) => info!("Exporting the traces to {}", endpoint),
+        (None, None) => {}
+    }
+}
+
+pub async fn shutdown() {
+    otlp::shutdown().await;
+}
+
+/// Span of each request, which also logs a line once the request is answered, in place of an
+/// access log
+pub struct RequestSpan;
+
+impl RootSpanBuilder for RequestSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        DefaultRootSpanBuilder::on_request_start(request)
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        let status = match outcome {
+            Ok(response) => response.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+        span.in_scope(|| info!(status = status.as_u16(), "Request answered"));
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
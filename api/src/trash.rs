@@ -0,0 +1,187 @@
+use actix_web::web::Json;
+use actix_web::{web, Error, HttpResponse, Responder};
+use entity::redirection::Model;
+use rus_core::chrono::Utc;
+use rus_core::sea_orm::DbConn;
+use rus_core::{Mutation, Query};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::admin::Admin;
+use crate::api::{find_redirection, pagination, DeletedResponse};
+use crate::audit::RequestActor;
+use crate::errors::ApiError;
+use crate::{AppCache, AppState, LookupParams, TrashParams};
+
+#[derive(Serialize, ToSchema)]
+pub struct TrashResponse {
+    #[schema(value_type = Vec<Redirection>)]
+    redirections: Vec<Model>,
+    page: u64,
+    redirections_per_page: u64,
+    pages_count: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EmptiedResponse {
+    error: bool,
+    message: String,
+    /// Number of redirections permanently deleted
+    purged: u64,
+}
+
+/// Finds a redirection of the trash from its short url, or from its numeric id when `by_id` is set
+async fn find_trashed(conn: &DbConn, id: String, lookup: &LookupParams) -> Result<Model, ApiError> {
+    let found = if lookup.by_id.unwrap_or(false) {
+        let numeric_id = id
+            .parse::<i32>()
+            .map_err(|err| ApiError::invalid_field("id", err))?;
+        Query::find_trashed_redirection_by_id(conn, numeric_id).await?
+    } else {
+        Query::find_short_url_owner(conn, id)
+            .await?
+            .filter(|redirection| redirection.deleted_at.is_some())
+    };
+    found.ok_or_else(|| ApiError::NotFound("Redirection not found in the trash".to_owned()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/trash",
+    params(TrashParams),
+    responses(
+        (status = 200, description = "Page of deleted and expired redirections, the most recently deleted first", body = TrashResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list(
+    _: Admin,
+    data: web::Data<AppState>,
+    params: web::Query<TrashParams>,
+) -> Result<impl Responder, Error> {
+    let params = params.into_inner();
+    let (page, redirections_per_page) =
+        pagination(params.page, params.redirections_per_page, &data.policies)?;
+
+    let (redirections, pages_count) =
+        Query::find_trash_in_page(&data.conn, page, redirections_per_page)
+            .await
+            .map_err(ApiError::from)?;
+
+    Ok(Json(TrashResponse {
+        redirections,
+        page,
+        redirections_per_page,
+        pages_count,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/trash/{id}/restore",
+    params(("id" = String, Path, description = "Short url of the redirection"), LookupParams),
+    responses(
+        (status = 200, description = "The restored redirection, expired ones get a new lifetime", body = Redirection),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+        (status = 404, description = "Unknown redirection", body = ErrorResponse),
+        (status = 409, description = "Redirection not in the trash", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn restore(
+    _: Admin,
+    data: web::Data<AppState>,
+    cache: web::Data<AppCache>,
+    actor: RequestActor,
+    id: web::Path<String>,
+    lookup: web::Query<LookupParams>,
+) -> Result<impl Responder, Error> {
+    let conn = &data.conn;
+    let id = id.into_inner();
+    let found = match find_trashed(conn, id.clone(), &lookup).await {
+        // a live redirection has nothing to be restored from
+        Err(ApiError::NotFound(message)) => {
+            return Err(match find_redirection(conn, id, &lookup).await {
+                Ok(_) => ApiError::Conflict("Redirection is not in the trash".to_owned()),
+                Err(_) => ApiError::NotFound(message),
+            }
+            .into())
+        }
+        found => found?,
+    };
+
+    let restored = Mutation::restore_redirection(
+        conn,
+        cache.cache.as_ref(),
+        &actor.0,
+        found.id,
+        data.link_lifetime,
+    )
+    .await
+    .map_err(ApiError::from)?;
+
+    Ok(Json(restored))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/trash/{id}",
+    params(("id" = String, Path, description = "Short url of the redirection"), LookupParams),
+    responses(
+        (status = 200, description = "Redirection permanently deleted", body = DeletedResponse),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+        (status = 404, description = "Redirection not in the trash", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn purge(
+    _: Admin,
+    data: web::Data<AppState>,
+    actor: RequestActor,
+    id: web::Path<String>,
+    lookup: web::Query<LookupParams>,
+) -> Result<impl Responder, Error> {
+    let conn = &data.conn;
+    let found = find_trashed(conn, id.into_inner(), &lookup).await?;
+
+    let purged = Mutation::purge_redirection(conn, &actor.0, found.id)
+        .await
+        .map_err(ApiError::from)?;
+
+    Ok(Json(DeletedResponse::new(
+        "Permanently deleted",
+        purged.id,
+        purged.short_url,
+    )))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/trash",
+    responses(
+        (status = 200, description = "Trash emptied", body = EmptiedResponse),
+        (status = 401, description = "Missing admin token", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn empty(
+    _: Admin,
+    data: web::Data<AppState>,
+    actor: RequestActor,
+) -> Result<impl Responder, Error> {
+    let purged = Mutation::purge_trash(&data.conn, &actor.0, Utc::now().naive_utc())
+        .await
+        .map_err(ApiError::from)?;
+
+    Ok(HttpResponse::Ok().json(EmptiedResponse {
+        error: false,
+        message: "Trash emptied".to_owned(),
+        purged,
+    }))
+}
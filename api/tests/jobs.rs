@@ -0,0 +1,130 @@
+use std::env;
+use std::time::Duration as StdDuration;
+
+use actix_rt::time::sleep;
+use rus_api::jobs::{JobLock, LockError};
+use rus_core::chrono::{Duration, Utc};
+use rus_core::redis::{self, aio::Connection};
+use rus_core::sea_orm::{Database, DatabaseConnection};
+use rus_core::{Cache, RedisCache};
+
+const INTERVAL: StdDuration = StdDuration::from_secs(2);
+
+/// The redis tests need a server, they are skipped unless `RUS_TEST_REDIS_URL` is set.
+/// Each test works in its own database, emptied first
+async fn redis_client(db: i64) -> Option<redis::Client> {
+    let url = match env::var("RUS_TEST_REDIS_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("RUS_TEST_REDIS_URL is not set, skipping");
+            return None;
+        }
+    };
+    let mut info = redis::IntoConnectionInfo::into_connection_info(url.as_str()).unwrap();
+    info.redis.db = db;
+    let client = redis::Client::open(info).unwrap();
+    let mut connection = client.get_async_connection().await.unwrap();
+    redis::cmd("FLUSHDB")
+        .query_async::<_, ()>(&mut connection)
+        .await
+        .unwrap();
+    Some(client)
+}
+
+/// The advisory lock tests need a PostgreSQL database, they are skipped unless
+/// `RUS_TEST_DATABASE_URL` is set
+async fn postgres() -> Option<DatabaseConnection> {
+    match env::var("RUS_TEST_DATABASE_URL") {
+        Ok(url) => Some(Database::connect(url).await.unwrap()),
+        Err(_) => {
+            eprintln!("RUS_TEST_DATABASE_URL is not set, skipping");
+            None
+        }
+    }
+}
+
+async fn exists(connection: &mut Connection, key: &str) -> bool {
+    redis::cmd("EXISTS")
+        .arg(key)
+        .query_async(connection)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn flushing_the_cache_keeps_the_job_locks() {
+    let Some(client) = redis_client(3).await else {
+        return;
+    };
+    let timeout = StdDuration::from_secs(1);
+    let lock = JobLock::redis(client.clone(), timeout).await.unwrap();
+    let other_replica = JobLock::redis(client.clone(), timeout).await.unwrap();
+    let cache = RedisCache::connect(client.clone(), timeout).await.unwrap();
+    let mut connection = client.get_async_connection().await.unwrap();
+
+    let ran = lock
+        .run("flush", INTERVAL, async {
+            let expires = Utc::now().naive_utc() + Duration::days(1);
+            cache
+                .add_entry("a".into(), "https://a.com".into(), expires)
+                .await
+                .unwrap();
+            cache.flush().await.unwrap();
+
+            assert!(exists(&mut connection, "rus:jobs:flush").await);
+            let skipped = other_replica.run("flush", INTERVAL, async {}).await;
+            assert!(matches!(skipped, Ok(None)));
+        })
+        .await;
+    assert!(matches!(ran, Ok(Some(()))));
+    assert_eq!(cache.try_get("a").await, None);
+}
+
+#[actix_web::test]
+async fn cancels_the_job_once_its_lease_is_lost() {
+    let Some(client) = redis_client(4).await else {
+        return;
+    };
+    let lock = JobLock::redis(client.clone(), StdDuration::from_secs(1))
+        .await
+        .unwrap();
+    let mut connection = client.get_async_connection().await.unwrap();
+
+    let ran = lock
+        .run("lost", INTERVAL, async {
+            // another replica took the lock over after the lease expired
+            redis::cmd("SET")
+                .arg("rus:jobs:lost")
+                .arg("other-replica")
+                .query_async::<_, ()>(&mut connection)
+                .await
+                .unwrap();
+            sleep(StdDuration::from_secs(30)).await;
+        })
+        .await;
+    assert!(matches!(ran, Err(LockError::Lost)));
+}
+
+#[actix_web::test]
+async fn runs_a_job_on_a_single_postgres_connection_at_a_time() {
+    let Some(conn) = postgres().await else {
+        return;
+    };
+    let lock = JobLock::Postgres(conn);
+    let other_replica = JobLock::Postgres(postgres().await.unwrap());
+
+    let ran = lock
+        .run("advisory", INTERVAL, async {
+            let skipped = other_replica.run("advisory", INTERVAL, async {}).await;
+            assert!(matches!(skipped, Ok(None)));
+            // the locks of the other jobs are independent
+            let other_job = other_replica.run("other", INTERVAL, async {}).await;
+            assert!(matches!(other_job, Ok(Some(()))));
+        })
+        .await;
+    assert!(matches!(ran, Ok(Some(()))));
+
+    // released at the end of the tick
+    let ran = other_replica.run("advisory", INTERVAL, async {}).await;
+    assert!(matches!(ran, Ok(Some(()))));
+}
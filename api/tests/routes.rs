@@ -0,0 +1,1195 @@
+use std::io;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::web::ServiceConfig;
+use actix_web::{test, web, App};
+use async_trait::async_trait;
+use entity::redirection;
+use migration::{Migrator, MigratorTrait};
+use rus_api::conf::{JobConfig, PoliciesConfig};
+use rus_api::jobs::JobRegistry;
+use rus_api::{configure, AppCache, AppState};
+use rus_core::chrono::{Duration, NaiveDateTime, Utc};
+use rus_core::errors::RusError;
+use rus_core::sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectOptions, Database, DatabaseConnection, EntityTrait,
+    IntoActiveModel, QueryFilter, Set,
+};
+use rus_core::{Cache, CacheStats, CachedRedirection, InMemoryCache, Mutation};
+use serde_json::{json, Value};
+
+/// Each connection to an in-memory database gets its own, so the pool holds a single one
+async fn database() -> DatabaseConnection {
+    let mut options = ConnectOptions::new("sqlite::memory:".to_owned());
+    options.max_connections(1).min_connections(1);
+    let conn = Database::connect(options).await.unwrap();
+    Migrator::up(&conn, None).await.unwrap();
+    conn
+}
+
+fn in_memory_cache() -> Arc<InMemoryCache> {
+    Arc::new(InMemoryCache::new(
+        NonZeroUsize::new(100).unwrap(),
+        1024 * 1024,
+    ))
+}
+
+fn app(
+    conn: &DatabaseConnection,
+    cache: &Arc<InMemoryCache>,
+    link_lifetime: Duration,
+) -> impl Fn(&mut ServiceConfig) {
+    let state =
+        AppState::new(conn.clone(), link_lifetime).with_admin_token(Some("token".to_owned()));
+    let cache = web::Data::new(AppCache::new(cache.clone(), Duration::minutes(1)));
+    configure(state, cache)
+}
+
+/// The cache is filled in the background once the redirection is served
+async fn wait_for_cache(cache: &InMemoryCache, key: &str) -> Option<CachedRedirection> {
+    for _ in 0..50 {
+        if let Some(cached) = cache.try_get(key).await {
+            return Some(cached);
+        }
+        actix_rt::time::sleep(StdDuration::from_millis(10)).await;
+    }
+    None
+}
+
+/// Creates a redirection through the API and returns its short url
+macro_rules! create {
+    ($app:expr, $long_url:expr) => {
+        create!($app, $long_url, json!({ "long_url": $long_url }))
+    };
+    ($app:expr, $long_url:expr, $form:expr) => {{
+        let request = test::TestRequest::post()
+            .uri("/api/v1/redirections")
+            .set_json($form)
+            .to_request();
+        let response = test::call_service(&$app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: Value = test::read_body_json(response).await;
+        body["message"]
+            .as_str()
+            .and_then(|message| message.split_whitespace().nth(1))
+            .unwrap()
+            .to_owned()
+    }};
+}
+
+/// Lists the redirections with the given query string, returns their short urls and the body
+macro_rules! list {
+    ($app:expr, $query:expr) => {{
+        let request = test::TestRequest::get()
+            .uri(&format!("/api/v1/redirections?{}", $query))
+            .to_request();
+        let response = test::call_service(&$app, request).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", $query);
+        let body: Value = test::read_body_json(response).await;
+        let short_urls: Vec<String> = body["redirections"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|redirection| redirection["short_url"].as_str().unwrap().to_owned())
+            .collect();
+        (short_urls, body)
+    }};
+}
+
+/// Sets the columns the listings are sorted by, which only the traffic changes
+async fn set_activity(
+    conn: &DatabaseConnection,
+    short_url: &str,
+    clicks: i64,
+    last_access_date: NaiveDateTime,
+    expiration_date: Option<NaiveDateTime>,
+) {
+    let mut model = redirection::Entity::find()
+        .filter(redirection::Column::ShortUrl.eq(short_url))
+        .one(conn)
+        .await
+        .unwrap()
+        .unwrap()
+        .into_active_model();
+    model.clicks = Set(clicks);
+    model.last_access_date = Set(last_access_date);
+    model.expiration_date = Set(expiration_date);
+    model.update(conn).await.unwrap();
+}
+
+#[actix_web::test]
+async fn creates_and_lists_redirections() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let short_url = create!(app, "https://example.com/created");
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/redirections")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    let redirections = body["redirections"].as_array().unwrap();
+    assert_eq!(redirections.len(), 1);
+    assert_eq!(redirections[0]["short_url"], short_url.as_str());
+    assert_eq!(redirections[0]["long_url"], "https://example.com/created");
+    assert_eq!(body["pages_count"], 1);
+}
+
+#[actix_web::test]
+async fn sorts_and_filters_the_listing() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let a = create!(app, "https://example.com/a");
+    let b = create!(app, "https://example.com/b");
+    let c = create!(app, "https://example.com/c");
+    let now = Utc::now().naive_utc();
+    set_activity(
+        &conn,
+        a.as_str(),
+        1,
+        now - Duration::hours(1),
+        Some(now + Duration::days(3)),
+    )
+    .await;
+    set_activity(
+        &conn,
+        b.as_str(),
+        5,
+        now - Duration::hours(3),
+        Some(now - Duration::days(1)),
+    )
+    .await;
+    set_activity(&conn, c.as_str(), 3, now - Duration::hours(2), None).await;
+
+    for (query, expected) in [
+        ("sort=id", [a.as_str(), b.as_str(), c.as_str()]),
+        ("sort=id&order=desc", [c.as_str(), b.as_str(), a.as_str()]),
+        // created within the same second, the ties are broken by id
+        ("sort=creation", [a.as_str(), b.as_str(), c.as_str()]),
+        (
+            "sort=creation&order=desc",
+            [c.as_str(), b.as_str(), a.as_str()],
+        ),
+        // links without an expiration date come last
+        ("sort=expiration", [b.as_str(), a.as_str(), c.as_str()]),
+        (
+            "sort=expiration&order=desc",
+            [c.as_str(), a.as_str(), b.as_str()],
+        ),
+        ("sort=last_access", [b.as_str(), c.as_str(), a.as_str()]),
+        (
+            "sort=last_access&order=desc",
+            [a.as_str(), c.as_str(), b.as_str()],
+        ),
+        ("sort=clicks", [a.as_str(), c.as_str(), b.as_str()]),
+        (
+            "sort=clicks&order=desc",
+            [b.as_str(), c.as_str(), a.as_str()],
+        ),
+    ] {
+        let (short_urls, _) = list!(app, query);
+        assert_eq!(short_urls, expected, "{}", query);
+    }
+
+    let (short_urls, _) = list!(app, "state=expired");
+    assert_eq!(short_urls, [b.as_str()]);
+    let (short_urls, _) = list!(app, "state=active&sort=clicks&order=desc");
+    assert_eq!(short_urls, [c.as_str(), a.as_str()]);
+}
+
+#[actix_web::test]
+async fn paginates_with_a_cursor() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let mut created = Vec::new();
+    for (clicks, long_url) in [
+        (4, "https://example.com/a"),
+        (2, "https://example.com/b"),
+        (4, "https://example.com/c"),
+        (1, "https://example.com/d"),
+        (3, "https://example.com/e"),
+    ] {
+        let short_url = create!(app, long_url);
+        set_activity(&conn, &short_url, clicks, Utc::now().naive_utc(), None).await;
+        created.push(short_url);
+    }
+    let [a, b, c, d, e] = <[String; 5]>::try_from(created).unwrap();
+
+    let (first, body) = list!(app, "sort=clicks&order=desc&redirections_per_page=2");
+    // equal clicks are ordered by id, in the requested direction
+    assert_eq!(first, [c.as_str(), a.as_str()]);
+    assert_eq!(body["pages_count"], 3);
+    let cursor = body["next_cursor"].as_str().unwrap().to_owned();
+
+    let (second, body) = list!(
+        app,
+        format!(
+            "sort=clicks&order=desc&redirections_per_page=2&after={}",
+            cursor
+        )
+    );
+    assert_eq!(second, [e.as_str(), b.as_str()]);
+    assert!(body.get("page").is_none());
+    let cursor = body["next_cursor"].as_str().unwrap().to_owned();
+
+    let (last, body) = list!(
+        app,
+        format!(
+            "sort=clicks&order=desc&redirections_per_page=2&after={}",
+            cursor
+        )
+    );
+    assert_eq!(last, [d.as_str()]);
+    assert!(body["next_cursor"].is_null());
+
+    // cursors only make sense for the sort they were produced by
+    let request = test::TestRequest::get()
+        .uri(&format!(
+            "/api/v1/redirections?sort=creation&after={}",
+            cursor
+        ))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn filters_on_the_host_of_the_destination() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let page = create!(app, "https://Example.com/page");
+    let port = create!(app, "http://example.com:8080");
+    let query = create!(app, "https://example.com?ref=home");
+    for long_url in [
+        "https://evil.test/?next=https://example.com/",
+        "https://example.com.evil.test/",
+        "https://sub.example.com/",
+        "https://exampleacom/",
+    ] {
+        let _ = create!(app, long_url);
+    }
+
+    let (short_urls, _) = list!(app, "domain=EXAMPLE.com");
+    assert_eq!(short_urls, [page.as_str(), port.as_str(), query.as_str()]);
+
+    // the wildcards of LIKE are matched literally
+    let (short_urls, _) = list!(app, "domain=example_com");
+    assert!(short_urls.is_empty());
+    let (short_urls, _) = list!(app, "domain=%25");
+    assert!(short_urls.is_empty());
+}
+
+#[actix_web::test]
+async fn searches_the_links() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let blog = "https://blog.example.com/tokio-tips";
+    let blog = create!(
+        app,
+        blog,
+        json!({ "long_url": blog, "title": "Tokio tips", "notes": "About the async RUNTIME" })
+    );
+    let docs = "https://docs.rs/tokio";
+    let docs = create!(
+        app,
+        docs,
+        json!({ "long_url": docs, "title": "Async runtime" })
+    );
+    let recipe = "https://cooking.test/pie";
+    let recipe = create!(
+        app,
+        recipe,
+        json!({ "long_url": recipe, "title": "Apple pie", "notes": "From grandma" })
+    );
+    let sale = create!(app, "https://example.com/save-100%25");
+
+    for (terms, expected) in [
+        // title and notes, ties are broken by recency
+        ("runtime", vec![&docs, &blog]),
+        // the url and the title of the older blog post both match
+        ("tokio", vec![&blog, &docs]),
+        // every term must match, in any field
+        ("tokio+async", vec![&blog, &docs]),
+        ("tokio+grandma", vec![]),
+        ("grandma", vec![&recipe]),
+        (recipe.as_str(), vec![&recipe]),
+        // the wildcards of LIKE are matched literally
+        ("%25", vec![&sale]),
+        ("_", vec![]),
+    ] {
+        let request = test::TestRequest::get()
+            .uri(&format!("/api/v1/search?q={}", terms))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        let found: Vec<_> = body["redirections"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|redirection| redirection["short_url"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(found.iter().collect::<Vec<_>>(), expected, "{}", terms);
+    }
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/search?q=tokio&redirections_per_page=1&page=2")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["pages_count"], 2);
+    assert_eq!(body["redirections"][0]["short_url"], docs.as_str());
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/search?q=+")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn rejects_invalid_urls() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::post()
+        .uri("/api/v1/redirections")
+        .set_json(json!({ "long_url": "not an url" }))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "long_url");
+}
+
+#[actix_web::test]
+async fn accepts_form_and_json_payloads() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::post()
+        .uri("/api/v1/redirections")
+        .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+        .set_payload("long_url=https%3A%2F%2Fexample.com%2Fform&title=From+a+form")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(response).await;
+    let short_url = body["message"]
+        .as_str()
+        .and_then(|message| message.split_whitespace().nth(1))
+        .unwrap()
+        .to_owned();
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["long_url"], "https://example.com/form");
+    assert_eq!(body["title"], "From a form");
+
+    let request = test::TestRequest::put()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .insert_header((
+            header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded; charset=utf-8",
+        ))
+        .set_payload("long_url=https%3A%2F%2Fexample.com%2Fedited")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = test::TestRequest::put()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .insert_header((header::CONTENT_TYPE, "application/vnd.rus+json"))
+        .set_payload(json!({ "long_url": "https://example.com/vendor" }).to_string())
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["long_url"], "https://example.com/vendor");
+
+    // form fields are validated like the JSON ones
+    let request = test::TestRequest::post()
+        .uri("/api/v1/redirections")
+        .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+        .set_payload("title=No+destination")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "long_url");
+}
+
+#[actix_web::test]
+async fn rejects_unsupported_content_types() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::post()
+        .uri("/api/v1/redirections")
+        .insert_header((header::CONTENT_TYPE, "text/plain"))
+        .set_payload("https://example.com/plain")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["error"], true);
+    assert_eq!(body["code"], "unsupported_media_type");
+    assert_eq!(body["message"], "Unsupported content type 'text/plain'");
+}
+
+#[actix_web::test]
+async fn reports_malformed_requests_as_json() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/malformed");
+
+    let request = test::TestRequest::post()
+        .uri("/api/v1/redirections")
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .set_payload(r#"{"long_url": "#)
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "bad_request");
+
+    let request = test::TestRequest::post()
+        .uri(&format!(
+            "/api/v1/redirections/{}/history/first/rollback",
+            short_url
+        ))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["error"], true);
+    assert_eq!(body["code"], "bad_request");
+}
+
+#[actix_web::test]
+async fn redirects_and_caches_the_destination() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/cached");
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://example.com/cached"
+    );
+    assert_eq!(
+        wait_for_cache(&cache, &short_url).await,
+        Some(CachedRedirection::Found(
+            "https://example.com/cached".to_owned()
+        ))
+    );
+}
+
+#[actix_web::test]
+async fn remembers_unknown_short_urls() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::get().uri("/unknown").to_request();
+    let response = test::call_service(&app, request).await;
+
+    // unknown short urls are left to the client side router
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        wait_for_cache(&cache, "unknown").await,
+        Some(CachedRedirection::Missing)
+    );
+}
+
+#[actix_web::test]
+async fn update_changes_the_destination() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/old");
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    test::call_service(&app, request).await;
+    assert!(wait_for_cache(&cache, &short_url).await.is_some());
+
+    let request = test::TestRequest::put()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .set_json(json!({ "long_url": "https://example.com/new" }))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(cache.try_get(&short_url).await, None);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://example.com/new"
+    );
+}
+
+/// Changes the destination right before the cache is filled, as an update committed between the
+/// read of the redirection and the fill would
+#[derive(Debug)]
+struct UpdatedBeforeFill {
+    cache: Arc<InMemoryCache>,
+    conn: DatabaseConnection,
+    filled: AtomicBool,
+}
+
+#[async_trait]
+impl Cache for UpdatedBeforeFill {
+    async fn try_get(&self, key: &str) -> Option<CachedRedirection> {
+        self.cache.try_get(key).await
+    }
+
+    async fn add_entry(
+        &self,
+        key: String,
+        value: String,
+        expires: NaiveDateTime,
+    ) -> Result<(), RusError> {
+        let model = redirection::Entity::find()
+            .filter(redirection::Column::ShortUrl.eq(key.as_str()))
+            .one(&self.conn)
+            .await?
+            .unwrap();
+        let mut model = model.into_active_model();
+        model.long_url = Set("https://example.com/new".to_owned());
+        model.update(&self.conn).await?;
+
+        self.cache.add_entry(key, value, expires).await?;
+        self.filled.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn add_missing(&self, key: String, ttl: Duration) -> Result<(), RusError> {
+        self.cache.add_missing(key, ttl).await
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), RusError> {
+        self.cache.remove(key).await
+    }
+
+    async fn flush(&self) -> Result<(), RusError> {
+        self.cache.flush().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats, RusError> {
+        self.cache.stats().await
+    }
+}
+
+#[actix_web::test]
+async fn doesnt_cache_a_destination_changed_during_the_fill() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let racing = Arc::new(UpdatedBeforeFill {
+        cache: cache.clone(),
+        conn: conn.clone(),
+        filled: AtomicBool::new(false),
+    });
+    let state = AppState::new(conn.clone(), Duration::days(1));
+    let app_cache = web::Data::new(AppCache::new(racing.clone(), Duration::minutes(1)));
+    let app = test::init_service(App::new().configure(configure(state, app_cache))).await;
+    let short_url = create!(app, "https://example.com/old");
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://example.com/old"
+    );
+
+    for _ in 0..50 {
+        if racing.filled.load(Ordering::SeqCst) && cache.try_get(&short_url).await.is_none() {
+            break;
+        }
+        actix_rt::time::sleep(StdDuration::from_millis(10)).await;
+    }
+    assert!(racing.filled.load(Ordering::SeqCst));
+    assert_eq!(cache.try_get(&short_url).await, None);
+}
+
+#[actix_web::test]
+async fn delete_removes_the_redirection() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/deleted");
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    test::call_service(&app, request).await;
+    assert!(wait_for_cache(&cache, &short_url).await.is_some());
+
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["short_url"], short_url.as_str());
+    assert_eq!(cache.try_get(&short_url).await, None);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn rolls_back_to_a_previous_destination() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/printed");
+    let request = test::TestRequest::put()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .set_json(json!({ "long_url": "https://example.com/typo" }))
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/api/v1/redirections/{}/history", short_url))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["versions"][0]["version"], 2);
+    assert_eq!(body["versions"][0]["long_url"], "https://example.com/typo");
+    assert_eq!(body["versions"][1]["version"], 1);
+
+    let request = test::TestRequest::post()
+        .uri(&format!(
+            "/api/v1/redirections/{}/history/5/rollback",
+            short_url
+        ))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = test::TestRequest::post()
+        .uri(&format!(
+            "/api/v1/redirections/{}/history/1/rollback",
+            short_url
+        ))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["long_url"], "https://example.com/printed");
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://example.com/printed"
+    );
+
+    // the rollback is a new version, the bad edit stays in the history
+    let request = test::TestRequest::get()
+        .uri(&format!("/api/v1/redirections/{}/history", short_url))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["versions"].as_array().unwrap().len(), 3);
+    assert_eq!(body["versions"][0]["version"], 3);
+    assert_eq!(
+        body["versions"][0]["long_url"],
+        "https://example.com/printed"
+    );
+}
+
+#[actix_web::test]
+async fn expired_redirections_are_removed() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    // links expire as soon as they are created
+    let app =
+        test::init_service(App::new().configure(app(&conn, &cache, Duration::days(-1)))).await;
+    let expired = create!(app, "https://example.com/expired");
+
+    let removed = Mutation::remove_expired_redirections(&conn, cache.as_ref())
+        .await
+        .unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].short_url, expired);
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/redirections")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["redirections"].as_array().unwrap().len(), 0);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", expired))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn enforces_link_policies() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let policies = PoliciesConfig {
+        blocked_domains: vec!["blocked.com".to_owned()],
+        ..Default::default()
+    };
+    let state = AppState::new(conn.clone(), Duration::days(1)).with_policies(policies);
+    let app_cache = web::Data::new(AppCache::new(cache.clone(), Duration::minutes(1)));
+    let app = test::init_service(App::new().configure(configure(state, app_cache))).await;
+
+    for long_url in ["https://www.blocked.com/page", "ftp://example.com/file"] {
+        let request = test::TestRequest::post()
+            .uri("/api/v1/redirections")
+            .set_json(json!({ "long_url": long_url }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let allowed = create!(app, "https://notblocked.com/page");
+    assert!(!allowed.is_empty());
+}
+
+#[actix_web::test]
+async fn reports_the_jobs_runs() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let every_second = JobConfig {
+        interval_secs: 1,
+        timeout_secs: 1,
+        ..Default::default()
+    };
+    let mut registry = JobRegistry::default();
+    registry.register("succeeds", &every_second);
+    registry.register("fails", &every_second);
+    registry.register(
+        "disabled",
+        &JobConfig {
+            enabled: false,
+            ..Default::default()
+        },
+    );
+    let registry = Arc::new(registry);
+    let state = AppState::new(conn.clone(), Duration::days(1))
+        .with_admin_token(Some("token".to_owned()))
+        .with_jobs(registry.clone());
+    let app_cache = web::Data::new(AppCache::new(cache.clone(), Duration::minutes(1)));
+    let app = test::init_service(App::new().configure(configure(state, app_cache))).await;
+
+    let succeeding = registry.clone();
+    actix_rt::spawn(async move { succeeding.schedule("succeeds", || async { Ok(3) }).await });
+    let failing = registry.clone();
+    actix_rt::spawn(async move {
+        failing
+            .schedule("fails", || async { Err(RusError::Unknown) })
+            .await
+    });
+
+    let mut jobs = Value::Null;
+    for _ in 0..30 {
+        let request = test::TestRequest::get()
+            .uri("/api/v1/admin/jobs")
+            .insert_header((header::AUTHORIZATION, "Bearer token"))
+            .to_request();
+        jobs = test::call_and_read_body_json(&app, request).await;
+        if jobs[0]["last_run"].is_object() && jobs[1]["last_run"].is_object() {
+            break;
+        }
+        actix_rt::time::sleep(StdDuration::from_millis(100)).await;
+    }
+
+    assert_eq!(jobs[0]["name"], "succeeds");
+    assert_eq!(jobs[0]["last_run"]["outcome"], "success");
+    assert_eq!(jobs[0]["last_run"]["affected_rows"], 3);
+    assert_eq!(jobs[1]["last_run"]["outcome"], "failure");
+    assert_eq!(jobs[1]["last_run"]["error"], "unknown_error");
+    assert_eq!(jobs[2]["enabled"], false);
+    assert!(jobs[2]["last_run"].is_null());
+}
+
+#[actix_web::test]
+async fn deleted_redirections_can_be_restored() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/trashed");
+
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/trash")
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["redirections"][0]["short_url"], short_url.as_str());
+    assert!(body["redirections"][0]["deleted_at"].is_string());
+
+    // trashed codes are never served
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        wait_for_cache(&cache, &short_url).await,
+        Some(CachedRedirection::Missing)
+    );
+
+    let request = test::TestRequest::post()
+        .uri(&format!("/api/v1/trash/{}/restore", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert!(body["deleted_at"].is_null());
+    assert_eq!(cache.try_get(&short_url).await, None);
+
+    let request = test::TestRequest::post()
+        .uri(&format!("/api/v1/trash/{}/restore", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "conflict");
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", short_url))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/trash")
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["redirections"].as_array().unwrap().len(), 0);
+}
+
+#[actix_web::test]
+async fn restored_expired_redirections_get_a_new_lifetime() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let expiring =
+        test::init_service(App::new().configure(app(&conn, &cache, Duration::days(-1)))).await;
+    let expired = create!(expiring, "https://example.com/expired");
+    Mutation::remove_expired_redirections(&conn, cache.as_ref())
+        .await
+        .unwrap();
+
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let request = test::TestRequest::post()
+        .uri(&format!("/api/v1/trash/{}/restore", expired))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let removed = Mutation::remove_expired_redirections(&conn, cache.as_ref())
+        .await
+        .unwrap();
+    assert!(removed.is_empty());
+}
+
+#[actix_web::test]
+async fn purged_redirections_are_gone() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/purged");
+
+    // only trashed redirections can be purged
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/trash/{}", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    test::call_service(&app, request).await;
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/trash/{}", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["short_url"], short_url.as_str());
+
+    let request = test::TestRequest::post()
+        .uri(&format!("/api/v1/trash/{}/restore", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn the_trash_requires_the_admin_token() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/guarded");
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .to_request();
+    test::call_service(&app, request).await;
+
+    for (method, uri) in [
+        (Method::GET, "/api/v1/trash".to_owned()),
+        (Method::POST, format!("/api/v1/trash/{}/restore", short_url)),
+        (Method::DELETE, format!("/api/v1/trash/{}", short_url)),
+    ] {
+        let request = test::TestRequest::default()
+            .method(method.clone())
+            .uri(&uri)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "unauthorized");
+
+        let request = test::TestRequest::default()
+            .method(method)
+            .uri(&uri)
+            .insert_header((header::AUTHORIZATION, "Bearer wrong"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    // still in the trash
+    let request = test::TestRequest::get()
+        .uri("/api/v1/trash")
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["redirections"][0]["short_url"], short_url.as_str());
+}
+
+#[actix_web::test]
+async fn records_the_audit_trail() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let state =
+        AppState::new(conn.clone(), Duration::days(1)).with_admin_token(Some("token".to_owned()));
+    let app_cache = web::Data::new(AppCache::new(cache.clone(), Duration::minutes(1)));
+    let app = test::init_service(App::new().configure(configure(state, app_cache))).await;
+    let short_url = create!(app, "https://example.com/old");
+    let other = create!(app, "https://example.com/other");
+
+    let request = test::TestRequest::put()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .set_json(json!({ "long_url": "https://example.com/new" }))
+        .to_request();
+    test::call_service(&app, request).await;
+    let request = test::TestRequest::delete()
+        .uri(&format!("/api/v1/redirections/{}", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    test::call_service(&app, request).await;
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/admin/audit")
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/api/v1/admin/audit?short_url={}", short_url))
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    let events = body["events"].as_array().unwrap();
+    let actions: Vec<_> = events.iter().map(|event| &event["action"]).collect();
+    assert_eq!(actions, ["delete", "update", "create"]);
+    assert_eq!(events[1]["actor_type"], "api_key");
+    assert_eq!(events[1]["actor"], "admin");
+    assert_eq!(events[1]["before"]["long_url"], "https://example.com/old");
+    assert_eq!(events[1]["after"]["long_url"], "https://example.com/new");
+    assert_eq!(events[2]["actor_type"], "ip");
+    assert!(events[2]["before"].is_null());
+
+    let request = test::TestRequest::get()
+        .uri("/api/v1/admin/audit?action=create")
+        .insert_header((header::AUTHORIZATION, "Bearer token"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["events"].as_array().unwrap().len(), 2);
+    assert_eq!(body["events"][0]["short_url"], other.as_str());
+}
+
+#[actix_web::test]
+async fn reports_readiness() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::get().uri("/healthz").to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["status"], "up");
+
+    let request = test::TestRequest::get().uri("/readyz").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["database"]["status"], "up");
+    assert!(body["database"]["latency_ms"].is_number());
+    assert_eq!(body["migrations"]["pending"], 0);
+    // the in-memory cache has no server to reach
+    assert!(body.get("redis").is_none());
+}
+
+/// Cache held by a redis server that can't be reached
+#[derive(Debug)]
+struct UnreachableRedis(Arc<InMemoryCache>);
+
+#[async_trait]
+impl Cache for UnreachableRedis {
+    async fn try_get(&self, key: &str) -> Option<CachedRedirection> {
+        self.0.try_get(key).await
+    }
+
+    async fn add_entry(
+        &self,
+        key: String,
+        value: String,
+        expires: NaiveDateTime,
+    ) -> Result<(), RusError> {
+        self.0.add_entry(key, value, expires).await
+    }
+
+    async fn add_missing(&self, key: String, ttl: Duration) -> Result<(), RusError> {
+        self.0.add_missing(key, ttl).await
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), RusError> {
+        self.0.remove(key).await
+    }
+
+    async fn flush(&self) -> Result<(), RusError> {
+        self.0.flush().await
+    }
+
+    async fn stats(&self) -> Result<CacheStats, RusError> {
+        self.0.stats().await
+    }
+
+    async fn ping(&self) -> Option<Result<(), RusError>> {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        Some(Err(RusError::Redis(refused.into())))
+    }
+}
+
+#[actix_web::test]
+async fn is_degraded_without_redis() {
+    let conn = database().await;
+    let state = AppState::new(conn.clone(), Duration::days(1));
+    let cache = Arc::new(UnreachableRedis(in_memory_cache()));
+    let app_cache = web::Data::new(AppCache::new(cache, Duration::minutes(1)));
+    let app = test::init_service(App::new().configure(configure(state, app_cache))).await;
+
+    let request = test::TestRequest::get().uri("/readyz").to_request();
+    let response = test::call_service(&app, request).await;
+    // the redirections fall back to the database
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["database"]["status"], "up");
+    assert_eq!(body["redis"]["status"], "degraded");
+    assert_eq!(body["redis"]["error"], "cache_error");
+}
+
+#[actix_web::test]
+async fn is_not_ready_until_migrated() {
+    let mut options = ConnectOptions::new("sqlite::memory:".to_owned());
+    options.max_connections(1).min_connections(1);
+    let conn = Database::connect(options).await.unwrap();
+    let cache = in_memory_cache();
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::get().uri("/readyz").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["database"]["status"], "up");
+    assert_eq!(body["migrations"]["status"], "down");
+    assert_eq!(body["migrations"]["error"], "pending_migrations");
+    assert!(body["migrations"]["pending"].as_u64().unwrap() > 0);
+}
+
+#[actix_web::test]
+async fn exposes_metrics_without_the_database() {
+    // the links can't be counted before the migrations
+    let mut options = ConnectOptions::new("sqlite::memory:".to_owned());
+    options.max_connections(1).min_connections(1);
+    let conn = Database::connect(options).await.unwrap();
+    let cache = in_memory_cache();
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+
+    let request = test::TestRequest::get().uri("/metrics").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    Migrator::up(&conn, None).await.unwrap();
+    let request = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert!(String::from_utf8_lossy(&body).contains("rus_live_links"));
+}
+
+#[actix_web::test]
+async fn exposes_prometheus_metrics() {
+    let (conn, cache) = (database().await, in_memory_cache());
+    let expiring =
+        test::init_service(App::new().configure(app(&conn, &cache, Duration::days(-1)))).await;
+    let expired = create!(expiring, "https://example.com/expired");
+    let app = test::init_service(App::new().configure(app(&conn, &cache, Duration::days(1)))).await;
+    let short_url = create!(app, "https://example.com/measured");
+    for _ in 0..2 {
+        let request = test::TestRequest::get()
+            .uri(&format!("/{}", short_url))
+            .to_request();
+        test::call_service(&app, request).await;
+        wait_for_cache(&cache, &short_url).await;
+    }
+
+    // expired links are no longer served, even before the job moves them to the trash
+    let request = test::TestRequest::get()
+        .uri(&format!("/{}", expired))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = test::TestRequest::get().uri("/metrics").to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    for expected in [
+        r#"rus_redirects_total{outcome="miss"}"#,
+        r#"rus_redirects_total{outcome="hit"}"#,
+        r#"rus_redirects_total{outcome="expired"}"#,
+        r#"rus_cache_lookups_total{backend="memory",result="hit"}"#,
+        r#"rus_http_request_duration_seconds_count{method="GET",route="/{id}",status="302"}"#,
+        "rus_live_links",
+    ] {
+        assert!(body.contains(expected), "{} is missing", expected);
+    }
+}
@@ -151,7 +151,7 @@ type alias DeletedResponse =
 deleteRedirection : Redirection -> Cmd ExternalMsg
 deleteRedirection red =
     Http.request
-        { url = "/api/v1/redirections/" ++ String.fromInt red.id
+        { url = "/api/v1/redirections/" ++ red.short_url
         , body = Http.emptyBody
         , expect = Http.expectJson DeletedRedirection deletedDecoder
         , method = "DELETE"
@@ -70,10 +70,12 @@ fetchRedirections model msg =
 
 redirectionDecoder : Decoder (List Redirection)
 redirectionDecoder =
-    Decoder.list
-        (Decoder.map3
-            Redirection
-            (Decoder.field "long_url" Decoder.string)
-            (Decoder.field "short_url" Decoder.string)
-            (Decoder.field "id" Decoder.int)
+    Decoder.field "redirections"
+        (Decoder.list
+            (Decoder.map3
+                Redirection
+                (Decoder.field "long_url" Decoder.string)
+                (Decoder.field "short_url" Decoder.string)
+                (Decoder.field "id" Decoder.int)
+            )
         )
@@ -0,0 +1,128 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::*;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use ::entity::{audit_event, redirection};
+
+/// Who made a change, recorded along with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    /// Named API key, never the key itself
+    ApiKey(String),
+    /// Address of an anonymous client
+    Ip(String),
+    /// Changes made by the maintenance jobs
+    System,
+}
+
+impl Actor {
+    fn kind(&self) -> &'static str {
+        match self {
+            Actor::ApiKey(_) => "api_key",
+            Actor::Ip(_) => "ip",
+            Actor::System => "system",
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Actor::ApiKey(name) | Actor::Ip(name) => name,
+            Actor::System => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Rollback,
+    Delete,
+    Restore,
+    Expire,
+    Purge,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Rollback => "rollback",
+            AuditAction::Delete => "delete",
+            AuditAction::Restore => "restore",
+            AuditAction::Expire => "expire",
+            AuditAction::Purge => "purge",
+        }
+    }
+}
+
+/// Criteria used to narrow down the audit trail, every criterion is optional
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub short_url: Option<String>,
+    pub action: Option<AuditAction>,
+    /// Name of the API key or ip address
+    pub actor: Option<String>,
+    pub after: Option<NaiveDateTime>,
+    pub before: Option<NaiveDateTime>,
+}
+
+impl AuditFilter {
+    pub(crate) fn condition(&self) -> Condition {
+        let mut condition = Condition::all();
+
+        if let Some(short_url) = &self.short_url {
+            condition = condition.add(audit_event::Column::ShortUrl.eq(short_url.as_str()));
+        }
+        if let Some(action) = self.action {
+            condition = condition.add(audit_event::Column::Action.eq(action.as_str()));
+        }
+        if let Some(actor) = &self.actor {
+            condition = condition.add(audit_event::Column::Actor.eq(actor.as_str()));
+        }
+        if let Some(after) = self.after {
+            condition = condition.add(audit_event::Column::CreatedAt.gte(after));
+        }
+        if let Some(before) = self.before {
+            condition = condition.add(audit_event::Column::CreatedAt.lt(before));
+        }
+
+        condition
+    }
+}
+
+fn snapshot(redirection: Option<&redirection::Model>) -> Option<serde_json::Value> {
+    redirection.and_then(|redirection| serde_json::to_value(redirection).ok())
+}
+
+/// Records a change, with the same connection as the change itself so that both are part of
+/// the same transaction
+pub(crate) async fn record<C: ConnectionTrait>(
+    db: &C,
+    action: AuditAction,
+    actor: &Actor,
+    before: Option<&redirection::Model>,
+    after: Option<&redirection::Model>,
+) -> Result<(), DbErr> {
+    let subject = match after.or(before) {
+        Some(subject) => subject,
+        None => return Ok(()),
+    };
+    audit_event::ActiveModel {
+        redirection_id: Set(subject.id),
+        short_url: Set(subject.short_url.to_owned()),
+        action: Set(action.as_str().to_owned()),
+        actor_type: Set(actor.kind().to_owned()),
+        actor: Set(actor.name().to_owned()),
+        before: Set(snapshot(before)),
+        after: Set(snapshot(after)),
+        created_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use prometheus::IntGauge;
+use redis::RedisError;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Past this many keys invalidated during an outage, the whole cache is flushed on recovery
+const MAX_PENDING_INVALIDATIONS: usize = 10_000;
+
+#[derive(Debug)]
+enum State {
+    Up,
+    Down {
+        failures: u32,
+        since: Instant,
+        retry_at: Instant,
+    },
+}
+
+/// Source of the current time of the backoff, controlled by the tests
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Invalidations that couldn't reach redis, replayed once it's back
+#[derive(Debug, Default)]
+pub struct PendingInvalidations {
+    pub keys: HashSet<String>,
+    pub flush: bool,
+}
+
+impl PendingInvalidations {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && !self.flush
+    }
+
+    fn add_key(&mut self, key: &str) {
+        if self.flush {
+            return;
+        }
+        if self.keys.len() >= MAX_PENDING_INVALIDATIONS {
+            self.add_flush();
+        } else {
+            self.keys.insert(key.to_owned());
+        }
+    }
+
+    /// A flush covers the later invalidations
+    fn add_flush(&mut self) {
+        self.keys.clear();
+        self.flush = true;
+    }
+}
+
+/// Tracks whether redis is reachable. Once a command failed, redis is left alone
+/// for an exponentially growing delay, after which the next command probes it again.
+///
+/// The `degraded` gauge is set to 1 while redis is down
+#[derive(Debug)]
+pub struct Health {
+    state: Mutex<State>,
+    pending: Mutex<PendingInvalidations>,
+    unpublished: Mutex<PendingInvalidations>,
+    clock: Arc<dyn Clock>,
+    degraded: IntGauge,
+}
+
+fn backoff(failures: u32) -> Duration {
+    MIN_BACKOFF
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Only the errors of the connection itself make redis unavailable, not the ones of a command
+pub(crate) fn is_unavailable(err: &RedisError) -> bool {
+    err.is_io_error()
+        || err.is_timeout()
+        || err.is_connection_refusal()
+        || err.is_connection_dropped()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Health {
+    pub fn new(clock: Arc<dyn Clock>, degraded: IntGauge) -> Health {
+        degraded.set(0);
+        Health {
+            state: Mutex::new(State::Up),
+            pending: Mutex::new(PendingInvalidations::default()),
+            unpublished: Mutex::new(PendingInvalidations::default()),
+            clock,
+            degraded,
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        matches!(*lock(&self.state), State::Up)
+    }
+
+    /// Whether a command may be sent to redis. While it's down, a single command
+    /// is let through each time the backoff delay elapses
+    pub fn should_try(&self) -> bool {
+        let mut state = lock(&self.state);
+        match *state {
+            State::Up => true,
+            State::Down {
+                failures,
+                since,
+                retry_at,
+            } => {
+                let now = self.clock.now();
+                if now < retry_at {
+                    return false;
+                }
+                // the other commands keep using the fallback while this one probes redis
+                *state = State::Down {
+                    failures,
+                    since,
+                    retry_at: now + backoff(failures),
+                };
+                true
+            }
+        }
+    }
+
+    pub fn failed(&self, err: &RedisError) {
+        let mut state = lock(&self.state);
+        let now = self.clock.now();
+        *state = match *state {
+            State::Up => {
+                warn!(
+                    "Redis is unavailable ({}), degrading to the fallback cache",
+                    err
+                );
+                self.degraded.set(1);
+                State::Down {
+                    failures: 1,
+                    since: now,
+                    retry_at: now + backoff(1),
+                }
+            }
+            State::Down {
+                failures, since, ..
+            } => {
+                let failures = failures.saturating_add(1);
+                let delay = backoff(failures);
+                warn!(
+                    "Redis is still unavailable ({}), next attempt in {:?}",
+                    err, delay
+                );
+                State::Down {
+                    failures,
+                    since,
+                    retry_at: now + delay,
+                }
+            }
+        };
+    }
+
+    /// Returns true when redis was down until now
+    pub fn succeeded(&self) -> bool {
+        let mut state = lock(&self.state);
+        if let State::Down { since, .. } = *state {
+            info!(
+                "Redis is available again, after {:?} of outage",
+                self.clock.now().saturating_duration_since(since)
+            );
+            *state = State::Up;
+            self.degraded.set(0);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn defer_invalidation(&self, key: &str) {
+        lock(&self.pending).add_key(key);
+    }
+
+    pub fn defer_flush(&self) {
+        lock(&self.pending).add_flush();
+    }
+
+    pub fn take_pending(&self) -> PendingInvalidations {
+        std::mem::take(&mut *lock(&self.pending))
+    }
+
+    /// Keeps an invalidation the other instances couldn't be told about, until redis is back
+    pub fn defer_publish(&self, key: &str) {
+        lock(&self.unpublished).add_key(key);
+    }
+
+    pub fn defer_publish_flush(&self) {
+        lock(&self.unpublished).add_flush();
+    }
+
+    pub fn take_unpublished(&self) -> PendingInvalidations {
+        std::mem::take(&mut *lock(&self.unpublished))
+    }
+}
@@ -0,0 +1,252 @@
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, Utc};
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tracing::instrument;
+
+use super::{Cache, CacheCounters, CacheStats, CachedRedirection};
+use crate::errors::RusError;
+
+/// Shards are only worth it for caches big enough to hold that many entries per shard
+const MIN_ENTRIES_PER_SHARD: usize = 1024;
+const MAX_SHARDS: usize = 64;
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    /// `None` for short urls known to be missing
+    long_url: Option<String>,
+    expires_at: NaiveDateTime,
+}
+
+fn entry_size(key: &str, entry: &CacheEntry) -> usize {
+    key.len() + entry.long_url.as_ref().map_or(0, String::len)
+}
+
+#[derive(Debug)]
+struct Shard {
+    lru: LruCache<String, CacheEntry>,
+    used_bytes: usize,
+    max_bytes: usize,
+    evictions: u64,
+}
+
+impl Shard {
+    fn get(&mut self, key: &str, now: NaiveDateTime) -> Option<CachedRedirection> {
+        let entry = self.lru.get(key)?.clone();
+
+        if entry.expires_at < now {
+            self.remove(key);
+            None
+        } else {
+            Some(match entry.long_url {
+                Some(long_url) => CachedRedirection::Found(long_url),
+                None => CachedRedirection::Missing,
+            })
+        }
+    }
+
+    fn insert(&mut self, key: String, entry: CacheEntry) {
+        let size = entry_size(&key, &entry);
+        if size > self.max_bytes {
+            // the previous value must not outlive the one that replaced it
+            self.remove(&key);
+            return;
+        }
+
+        self.used_bytes += size;
+        let replaced_key = key.to_owned();
+        if let Some((old_key, old_entry)) = self.lru.push(key, entry) {
+            // either the previous value of the same key, or the evicted least recently used entry
+            self.used_bytes -= entry_size(&old_key, &old_entry);
+            if old_key != replaced_key {
+                self.evictions += 1;
+            }
+        }
+        while self.used_bytes > self.max_bytes {
+            match self.lru.pop_lru() {
+                Some((old_key, old_entry)) => {
+                    self.used_bytes -= entry_size(&old_key, &old_entry);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.lru.pop(key) {
+            self.used_bytes -= entry_size(key, &entry);
+        }
+    }
+
+    fn purge_expired(&mut self, now: NaiveDateTime) -> usize {
+        let expired: Vec<String> = self
+            .lru
+            .iter()
+            .filter(|(_, entry)| entry.expires_at < now)
+            .map(|(key, _)| key.to_owned())
+            .collect();
+
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    fn clear(&mut self) {
+        self.lru.clear();
+        self.used_bytes = 0;
+    }
+}
+
+/// Bounded in-memory cache, the least recently used entries are evicted
+/// once either the maximum number of entries or the maximum size is reached.
+///
+/// Entries are spread over independently locked shards, so concurrent requests
+/// rarely wait on each other
+#[derive(Debug)]
+pub struct InMemoryCache {
+    shards: Vec<Mutex<Shard>>,
+    counters: CacheCounters,
+}
+
+impl InMemoryCache {
+    pub fn new(max_entries: NonZeroUsize, max_bytes: usize) -> InMemoryCache {
+        let shards_count = Self::shards_count(max_entries);
+        let entries_per_shard = max_entries.get().saturating_add(shards_count - 1) / shards_count;
+        let bytes_per_shard = max_bytes / shards_count;
+
+        InMemoryCache {
+            shards: (0..shards_count)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        lru: LruCache::new(
+                            NonZeroUsize::new(entries_per_shard).unwrap_or(max_entries),
+                        ),
+                        used_bytes: 0,
+                        max_bytes: bytes_per_shard,
+                        evictions: 0,
+                    })
+                })
+                .collect(),
+            counters: CacheCounters::new("memory"),
+        }
+    }
+
+    /// Number of shards used for a cache of `max_entries`, `max_bytes` is split evenly between them
+    pub fn shards_count(max_entries: NonZeroUsize) -> usize {
+        (max_entries.get() / MIN_ENTRIES_PER_SHARD)
+            .clamp(1, MAX_SHARDS)
+            .next_power_of_two()
+    }
+
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|index| self.lock(index).lru.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the cached keys and urls, in bytes
+    pub fn used_bytes(&self) -> usize {
+        (0..self.shards.len())
+            .map(|index| self.lock(index).used_bytes)
+            .sum()
+    }
+
+    pub fn clear(&self) {
+        for index in 0..self.shards.len() {
+            self.lock(index).clear();
+        }
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        // the number of shards is a power of two
+        hasher.finish() as usize & (self.shards.len() - 1)
+    }
+
+    /// A panic while holding the lock can't leave a shard in an inconsistent state,
+    /// so a poisoned lock is still usable
+    fn lock(&self, index: usize) -> MutexGuard<'_, Shard> {
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn shard(&self, key: &str) -> MutexGuard<'_, Shard> {
+        self.lock(self.shard_index(key))
+    }
+}
+
+#[async_trait]
+impl Cache for InMemoryCache {
+    #[instrument(name = "InMemoryCache::try_get", skip(self))]
+    async fn try_get(&self, key: &str) -> Option<CachedRedirection> {
+        let now = Utc::now().naive_utc();
+        let cached = self.shard(key).get(key, now);
+        self.counters.record(&cached);
+        cached
+    }
+
+    #[instrument(name = "InMemoryCache::add_entry", skip(self, value))]
+    async fn add_entry(
+        &self,
+        key: String,
+        value: String,
+        expires: NaiveDateTime,
+    ) -> Result<(), RusError> {
+        if expires < Utc::now().naive_utc() {
+            return Ok(());
+        }
+
+        let entry = CacheEntry {
+            long_url: Some(value),
+            expires_at: expires,
+        };
+        self.shard(&key).insert(key, entry);
+        Ok(())
+    }
+
+    #[instrument(name = "InMemoryCache::add_missing", skip(self))]
+    async fn add_missing(&self, key: String, ttl: Duration) -> Result<(), RusError> {
+        let entry = CacheEntry {
+            long_url: None,
+            expires_at: Utc::now().naive_utc() + ttl,
+        };
+        self.shard(&key).insert(key, entry);
+        Ok(())
+    }
+
+    #[instrument(name = "InMemoryCache::remove", skip(self))]
+    async fn remove(&self, key: &str) -> Result<(), RusError> {
+        self.shard(key).remove(key);
+        Ok(())
+    }
+
+    async fn purge_expired(&self) -> usize {
+        let now = Utc::now().naive_utc();
+        (0..self.shards.len())
+            .map(|index| self.lock(index).purge_expired(now))
+            .sum()
+    }
+
+    async fn flush(&self) -> Result<(), RusError> {
+        self.clear();
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<CacheStats, RusError> {
+        let evictions = (0..self.shards.len())
+            .map(|index| self.lock(index).evictions)
+            .sum();
+        Ok(self.counters.stats(self.len() as u64, evictions))
+    }
+}
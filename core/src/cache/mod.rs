@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use utoipa::ToSchema;
+
+use crate::errors::RusError;
+use crate::metrics::CACHE_LOOKUPS;
+
+mod health;
+mod memory;
+mod redis;
+mod tiered;
+
+pub use self::health::{Clock, Health, PendingInvalidations, SystemClock};
+pub use self::memory::InMemoryCache;
+pub use self::redis::RedisCache;
+pub use self::tiered::TieredCache;
+
+/// Cached outcome of the lookup of a short url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachedRedirection {
+    Found(String),
+    /// The short url is known not to exist in the database
+    Missing,
+}
+
+/// Snapshot of the activity of a cache since the start of the process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub backend: &'static str,
+    /// Number of cached entries
+    pub size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for new ones
+    pub evictions: u64,
+    /// Whether the backend is temporarily replaced by its in-memory fallback
+    pub degraded: bool,
+}
+
+/// Lookup counters shared by the backends, also exported to prometheus
+#[derive(Debug)]
+pub(crate) struct CacheCounters {
+    backend: &'static str,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheCounters {
+    pub(crate) fn new(backend: &'static str) -> CacheCounters {
+        CacheCounters {
+            backend,
+            hits: AtomicU64::default(),
+            misses: AtomicU64::default(),
+        }
+    }
+
+    pub(crate) fn record(&self, cached: &Option<CachedRedirection>) {
+        let (counter, result) = if cached.is_some() {
+            (&self.hits, "hit")
+        } else {
+            (&self.misses, "miss")
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        CACHE_LOOKUPS
+            .with_label_values(&[self.backend, result])
+            .inc();
+    }
+
+    pub(crate) fn stats(&self, size: u64, evictions: u64) -> CacheStats {
+        CacheStats {
+            backend: self.backend,
+            size,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions,
+            degraded: false,
+        }
+    }
+}
+
+/// Storage of the short url -> long url associations, to avoid hitting the database on each redirection
+#[async_trait]
+pub trait Cache: Debug + Send + Sync {
+    /// `None` when the cache knows nothing about the key
+    async fn try_get(&self, key: &str) -> Option<CachedRedirection>;
+
+    /// Caches the long url until `expires`, `NaiveDateTime::MAX` for the links that never expire.
+    /// Nothing is cached once `expires` is reached
+    async fn add_entry(
+        &self,
+        key: String,
+        value: String,
+        expires: NaiveDateTime,
+    ) -> Result<(), RusError>;
+
+    /// Remembers for `ttl` that the key doesn't exist, creating it must invalidate this entry
+    async fn add_missing(&self, key: String, ttl: Duration) -> Result<(), RusError>;
+
+    /// Invalidates the entry of the given key, if any
+    async fn remove(&self, key: &str) -> Result<(), RusError>;
+
+    /// Drops the entries that expired, returns how many were removed.
+    /// Backends handling the expiry on their own don't need to do anything
+    async fn purge_expired(&self) -> usize {
+        0
+    }
+
+    /// Removes every entry
+    async fn flush(&self) -> Result<(), RusError>;
+
+    async fn stats(&self) -> Result<CacheStats, RusError>;
+
+    /// Checks that the server holding the entries answers, `None` for the backends living in
+    /// the process memory
+    async fn ping(&self) -> Option<Result<(), RusError>> {
+        None
+    }
+}
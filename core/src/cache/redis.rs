@@ -0,0 +1,394 @@
+use async_trait::async_trait;
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, InfoDict, RedisError, RedisResult};
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{instrument, warn};
+
+use super::health::{is_unavailable, Health, SystemClock};
+use super::{Cache, CacheCounters, CacheStats, CachedRedirection, InMemoryCache};
+use crate::errors::RusError;
+use crate::metrics::CACHE_DEGRADED;
+
+/// Redis backed cache, sharing a single multiplexed connection between all the requests.
+/// The connection is transparently re-established when it drops.
+///
+/// While redis is unavailable, the commands are served by an optional in-memory fallback,
+/// and redis is probed again with an exponential backoff.
+///
+/// The keys are prefixed with `rus:cache:`, so flushing the cache leaves the other keys of the
+/// database alone, such as the locks of the jobs
+pub struct RedisCache {
+    connection: ConnectionManager,
+    timeout: Duration,
+    counters: CacheCounters,
+    health: Health,
+    fallback: Option<InMemoryCache>,
+}
+
+impl Debug for RedisCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisCache")
+            .field("timeout", &self.timeout)
+            .field("health", &self.health)
+            .finish()
+    }
+}
+
+/// Value stored for the short urls known to be missing, a long url is never empty
+const MISSING_MARKER: &str = "";
+
+const KEY_PREFIX: &str = "rus:cache:";
+/// Keys deleted by each round trip of a flush
+const FLUSH_BATCH: usize = 1000;
+
+/// Channel the invalidated keys are published on, so every instance drops them from its local tier
+pub(super) const INVALIDATION_CHANNEL: &str = "rus:invalidations";
+/// Published instead of a key when the whole cache is flushed, short urls are alphanumeric
+pub(super) const FLUSH_MESSAGE: &str = "*";
+
+fn namespaced(key: &str) -> String {
+    format!("{}{}", KEY_PREFIX, key)
+}
+
+/// Bounds the duration of a redis command, so a slow server can't hold the redirections
+async fn with_timeout<T>(
+    timeout: Duration,
+    command: impl Future<Output = RedisResult<T>>,
+) -> RedisResult<T> {
+    tokio::time::timeout(timeout, command)
+        .await
+        .unwrap_or_else(|_| {
+            Err(RedisError::from(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Redis command timed out",
+            )))
+        })
+}
+
+impl RedisCache {
+    pub async fn connect(client: redis::Client, timeout: Duration) -> Result<RedisCache, RusError> {
+        let connection = with_timeout(timeout, ConnectionManager::new(client)).await?;
+        Ok(RedisCache {
+            connection,
+            timeout,
+            counters: CacheCounters::new("redis"),
+            health: Health::new(Arc::new(SystemClock), CACHE_DEGRADED.clone()),
+            fallback: None,
+        })
+    }
+
+    /// Keeps caching in memory while redis is unavailable, instead of missing every lookup
+    pub fn with_fallback(mut self, fallback: InMemoryCache) -> RedisCache {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Whether redis is currently considered unavailable
+    pub fn is_degraded(&self) -> bool {
+        !self.health.is_up()
+    }
+
+    /// Sends the command unless redis is known to be unavailable.
+    /// Returns `None` when redis couldn't be reached, the caller then uses the fallback
+    async fn run<T>(
+        &self,
+        command: impl Future<Output = RedisResult<T>>,
+    ) -> Result<Option<T>, RusError> {
+        self.run_bounded(with_timeout(self.timeout, command)).await
+    }
+
+    /// Same as `run`, for the commands bounding the duration of each of their round trips
+    async fn run_bounded<T>(
+        &self,
+        command: impl Future<Output = RedisResult<T>>,
+    ) -> Result<Option<T>, RusError> {
+        if !self.health.should_try() {
+            return Ok(None);
+        }
+
+        match command.await {
+            Ok(value) => {
+                if self.health.succeeded() {
+                    self.recover().await;
+                }
+                Ok(Some(value))
+            }
+            Err(err) if is_unavailable(&err) => {
+                self.health.failed(&err);
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Deletes the keys of the cache in batches, as `FLUSHDB` would also drop the other keys.
+    /// The entries added during the flush may be kept
+    async fn delete_cache_keys(&self) -> RedisResult<()> {
+        let mut connection = self.connection.clone();
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = with_timeout(
+                self.timeout,
+                redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(format!("{}*", KEY_PREFIX))
+                    .arg("COUNT")
+                    .arg(FLUSH_BATCH)
+                    .query_async(&mut connection),
+            )
+            .await?;
+            if !keys.is_empty() {
+                with_timeout(self.timeout, connection.del::<_, ()>(keys)).await?;
+            }
+            if next == 0 {
+                return Ok(());
+            }
+            cursor = next;
+        }
+    }
+
+    /// Tells the instances sharing this cache to drop the key from their local tier.
+    /// Published once redis is back when it's unavailable
+    pub async fn publish_invalidation(&self, key: &str) -> Result<(), RusError> {
+        let mut connection = self.connection.clone();
+        if self
+            .run(connection.publish::<_, _, ()>(INVALIDATION_CHANNEL, key))
+            .await?
+            .is_none()
+        {
+            self.health.defer_publish(key);
+        }
+        Ok(())
+    }
+
+    /// Same as `publish_invalidation`, for the whole cache
+    pub async fn publish_flush(&self) -> Result<(), RusError> {
+        let mut connection = self.connection.clone();
+        if self
+            .run(connection.publish::<_, _, ()>(INVALIDATION_CHANNEL, FLUSH_MESSAGE))
+            .await?
+            .is_none()
+        {
+            self.health.defer_publish_flush();
+        }
+        Ok(())
+    }
+
+    /// Applies the invalidations missed during the outage, the entries still in redis may be stale
+    async fn recover(&self) {
+        if let Some(fallback) = &self.fallback {
+            fallback.clear();
+        }
+        if let Err(err) = self.replay_deletions().await {
+            warn!("Failed to replay the cache invalidations : {}", err);
+            if is_unavailable(&err) {
+                self.health.failed(&err);
+            }
+            return;
+        }
+        // published after the deletions, so the other instances don't read the stale entries again
+        if let Err(err) = self.replay_publications().await {
+            warn!("Failed to publish the cache invalidations : {}", err);
+            if is_unavailable(&err) {
+                self.health.failed(&err);
+            }
+        }
+    }
+
+    async fn replay_deletions(&self) -> RedisResult<()> {
+        let pending = self.health.take_pending();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let replayed = if pending.flush {
+            self.delete_cache_keys().await
+        } else {
+            let mut connection = self.connection.clone();
+            let keys: Vec<String> = pending.keys.iter().map(|key| namespaced(key)).collect();
+            with_timeout(self.timeout, connection.del::<_, ()>(keys)).await
+        };
+
+        if replayed.is_err() {
+            if pending.flush {
+                self.health.defer_flush();
+            }
+            for key in &pending.keys {
+                self.health.defer_invalidation(key);
+            }
+        }
+        replayed
+    }
+
+    async fn replay_publications(&self) -> RedisResult<()> {
+        let unpublished = self.health.take_unpublished();
+        if unpublished.is_empty() {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        if unpublished.flush {
+            pipe.publish(INVALIDATION_CHANNEL, FLUSH_MESSAGE).ignore();
+        } else {
+            for key in &unpublished.keys {
+                pipe.publish(INVALIDATION_CHANNEL, key).ignore();
+            }
+        }
+        let mut connection = self.connection.clone();
+        let published =
+            with_timeout(self.timeout, pipe.query_async::<_, ()>(&mut connection)).await;
+
+        if published.is_err() {
+            if unpublished.flush {
+                self.health.defer_publish_flush();
+            }
+            for key in &unpublished.keys {
+                self.health.defer_publish(key);
+            }
+        }
+        published
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    #[instrument(name = "RedisCache::try_get", skip(self))]
+    async fn try_get(&self, key: &str) -> Option<CachedRedirection> {
+        let mut connection = self.connection.clone();
+        let cached = match self
+            .run(connection.get::<_, Option<String>>(namespaced(key)))
+            .await
+        {
+            Ok(Some(value)) => value.map(|long_url| {
+                if long_url == MISSING_MARKER {
+                    CachedRedirection::Missing
+                } else {
+                    CachedRedirection::Found(long_url)
+                }
+            }),
+            Ok(None) => match &self.fallback {
+                Some(fallback) => fallback.try_get(key).await,
+                None => None,
+            },
+            Err(_) => None,
+        };
+        self.counters.record(&cached);
+        cached
+    }
+
+    #[instrument(name = "RedisCache::add_entry", skip(self, value))]
+    async fn add_entry(
+        &self,
+        key: String,
+        value: String,
+        expires: NaiveDateTime,
+    ) -> Result<(), RusError> {
+        // a plain SET would cache an expired link forever, and SETEX rejects a ttl of 0
+        let secs = match usize::try_from((expires - Utc::now().naive_utc()).num_seconds()) {
+            Ok(secs) if secs > 0 => secs,
+            _ => return Ok(()),
+        };
+        let mut connection = self.connection.clone();
+        let sent = self
+            .run(connection.set_ex::<_, &str, ()>(namespaced(&key), &value, secs))
+            .await?;
+        match (sent, &self.fallback) {
+            (None, Some(fallback)) => fallback.add_entry(key, value, expires).await,
+            _ => Ok(()),
+        }
+    }
+
+    #[instrument(name = "RedisCache::add_missing", skip(self))]
+    async fn add_missing(&self, key: String, ttl: ChronoDuration) -> Result<(), RusError> {
+        let secs = match usize::try_from(ttl.num_seconds()) {
+            Ok(secs) if secs > 0 => secs,
+            _ => return Ok(()),
+        };
+        let mut connection = self.connection.clone();
+        let sent = self
+            .run(connection.set_ex::<_, &str, ()>(namespaced(&key), MISSING_MARKER, secs))
+            .await?;
+        match (sent, &self.fallback) {
+            (None, Some(fallback)) => fallback.add_missing(key, ttl).await,
+            _ => Ok(()),
+        }
+    }
+
+    #[instrument(name = "RedisCache::remove", skip(self))]
+    async fn remove(&self, key: &str) -> Result<(), RusError> {
+        if let Some(fallback) = &self.fallback {
+            fallback.remove(key).await?;
+        }
+        let mut connection = self.connection.clone();
+        if self
+            .run(connection.del::<_, ()>(namespaced(key)))
+            .await?
+            .is_none()
+        {
+            self.health.defer_invalidation(key);
+        }
+        Ok(())
+    }
+
+    async fn purge_expired(&self) -> usize {
+        match &self.fallback {
+            Some(fallback) => fallback.purge_expired().await,
+            None => 0,
+        }
+    }
+
+    async fn flush(&self) -> Result<(), RusError> {
+        if let Some(fallback) = &self.fallback {
+            fallback.clear();
+        }
+        let flushed = self.run_bounded(self.delete_cache_keys()).await?;
+        if flushed.is_none() {
+            self.health.defer_flush();
+        }
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<CacheStats, RusError> {
+        let mut connection = self.connection.clone();
+        // also counts the few other keys of the database, counting the cache keys needs a scan
+        let size: Option<u64> = self
+            .run(redis::cmd("DBSIZE").query_async(&mut connection))
+            .await?;
+        let info: Option<InfoDict> = self
+            .run(redis::cmd("INFO").arg("stats").query_async(&mut connection))
+            .await?;
+
+        let mut stats = match (size, info) {
+            (Some(size), Some(info)) => {
+                let evictions = info.get("evicted_keys").unwrap_or_default();
+                self.counters.stats(size, evictions)
+            }
+            _ => {
+                let size = match &self.fallback {
+                    Some(fallback) => fallback.len() as u64,
+                    None => 0,
+                };
+                self.counters.stats(size, 0)
+            }
+        };
+        stats.degraded = self.is_degraded();
+        Ok(stats)
+    }
+
+    /// Always reaches redis, even while it is considered unavailable
+    async fn ping(&self) -> Option<Result<(), RusError>> {
+        let mut connection = self.connection.clone();
+        let pong = with_timeout(
+            self.timeout,
+            redis::cmd("PING").query_async::<_, ()>(&mut connection),
+        )
+        .await;
+        Some(pong.map_err(RusError::from))
+    }
+}
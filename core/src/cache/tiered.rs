@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, Utc};
+use futures_util::StreamExt;
+use redis::RedisResult;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+use tracing::{info, instrument, warn};
+
+use super::redis::{FLUSH_MESSAGE, INVALIDATION_CHANNEL};
+use super::{Cache, CacheCounters, CacheStats, CachedRedirection, InMemoryCache, RedisCache};
+use crate::errors::RusError;
+
+const MIN_RESUBSCRIBE_DELAY: StdDuration = StdDuration::from_secs(1);
+const MAX_RESUBSCRIBE_DELAY: StdDuration = StdDuration::from_secs(30);
+
+/// Small per-process cache in front of the redis cache shared by all the instances.
+///
+/// Invalidations are broadcast through redis pub/sub, the ones published while redis is
+/// unavailable are sent once it's back. Delivery isn't guaranteed, so local entries are only kept for `local_ttl` to bound how stale they can get
+#[derive(Debug)]
+pub struct TieredCache {
+    local: Arc<InMemoryCache>,
+    remote: RedisCache,
+    local_ttl: Duration,
+    counters: CacheCounters,
+}
+
+impl TieredCache {
+    /// Must be called from within a tokio runtime, the invalidations are received by a background task
+    pub async fn connect(
+        client: redis::Client,
+        timeout: StdDuration,
+        local: InMemoryCache,
+        local_ttl: Duration,
+    ) -> Result<TieredCache, RusError> {
+        let remote = RedisCache::connect(client.clone(), timeout).await?;
+        let local = Arc::new(local);
+
+        tokio::spawn(listen_invalidations(client, local.clone()));
+        Ok(TieredCache {
+            local,
+            remote,
+            local_ttl,
+            counters: CacheCounters::new("tiered"),
+        })
+    }
+
+    /// Looks up the local tier first, then redis, and fills the local tier on the way back
+    async fn lookup(&self, key: &str) -> Option<CachedRedirection> {
+        if let Some(cached) = self.local.try_get(key).await {
+            return Some(cached);
+        }
+
+        let cached = self.remote.try_get(key).await?;
+        let now = Utc::now().naive_utc();
+        match &cached {
+            CachedRedirection::Found(long_url) => {
+                self.local
+                    .add_entry(key.to_owned(), long_url.to_owned(), now + self.local_ttl)
+                    .await
+                    .ok();
+            }
+            CachedRedirection::Missing => {
+                self.local
+                    .add_missing(key.to_owned(), self.local_ttl)
+                    .await
+                    .ok();
+            }
+        }
+        Some(cached)
+    }
+
+    fn local_expiration(&self, expires: NaiveDateTime) -> NaiveDateTime {
+        expires.min(Utc::now().naive_utc() + self.local_ttl)
+    }
+}
+
+async fn subscribe(client: &redis::Client, local: &InMemoryCache) -> RedisResult<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(INVALIDATION_CHANNEL).await?;
+    info!("Listening to cache invalidations");
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        match message.get_payload::<String>() {
+            Ok(key) if key == FLUSH_MESSAGE => local.clear(),
+            Ok(key) => {
+                local.remove(&key).await.ok();
+            }
+            Err(err) => warn!("Invalid cache invalidation message : {}", err),
+        }
+    }
+    Ok(())
+}
+
+async fn listen_invalidations(client: redis::Client, local: Arc<InMemoryCache>) {
+    let mut delay = MIN_RESUBSCRIBE_DELAY;
+    loop {
+        let subscribed = subscribe(&client, &local).await;
+        // invalidations may have been missed while disconnected
+        local.clear();
+
+        match subscribed {
+            // the subscription was lost after being established
+            Ok(()) => {
+                tokio::time::sleep(MIN_RESUBSCRIBE_DELAY).await;
+                delay = MIN_RESUBSCRIBE_DELAY;
+            }
+            Err(err) => {
+                warn!(
+                    "Cache invalidations subscription failed ({}), retrying in {:?}",
+                    err, delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RESUBSCRIBE_DELAY);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Cache for TieredCache {
+    #[instrument(name = "TieredCache::try_get", skip(self))]
+    async fn try_get(&self, key: &str) -> Option<CachedRedirection> {
+        let cached = self.lookup(key).await;
+        self.counters.record(&cached);
+        cached
+    }
+
+    #[instrument(name = "TieredCache::add_entry", skip(self, value))]
+    async fn add_entry(
+        &self,
+        key: String,
+        value: String,
+        expires: NaiveDateTime,
+    ) -> Result<(), RusError> {
+        self.local
+            .add_entry(
+                key.to_owned(),
+                value.to_owned(),
+                self.local_expiration(expires),
+            )
+            .await?;
+        self.remote.add_entry(key, value, expires).await
+    }
+
+    #[instrument(name = "TieredCache::add_missing", skip(self))]
+    async fn add_missing(&self, key: String, ttl: Duration) -> Result<(), RusError> {
+        self.local
+            .add_missing(key.to_owned(), ttl.min(self.local_ttl))
+            .await?;
+        self.remote.add_missing(key, ttl).await
+    }
+
+    #[instrument(name = "TieredCache::remove", skip(self))]
+    async fn remove(&self, key: &str) -> Result<(), RusError> {
+        self.local.remove(key).await?;
+        self.remote.remove(key).await?;
+        self.remote.publish_invalidation(key).await
+    }
+
+    async fn purge_expired(&self) -> usize {
+        self.local.purge_expired().await
+    }
+
+    async fn flush(&self) -> Result<(), RusError> {
+        self.local.clear();
+        self.remote.flush().await?;
+        self.remote.publish_flush().await
+    }
+
+    /// Hits are the lookups served by either tier
+    async fn stats(&self) -> Result<CacheStats, RusError> {
+        let local = self.local.stats().await?;
+        let remote = self.remote.stats().await?;
+        let mut stats = self
+            .counters
+            .stats(remote.size, local.evictions + remote.evictions);
+        stats.degraded = remote.degraded;
+        Ok(stats)
+    }
+
+    async fn ping(&self) -> Option<Result<(), RusError>> {
+        self.remote.ping().await
+    }
+}
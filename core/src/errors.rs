@@ -7,6 +7,15 @@ pub enum RusError {
     #[display(fmt = "Access forbidden")]
     Forbidden,
 
+    #[display(fmt = "Authentication required")]
+    Unauthorized,
+
+    #[display(fmt = "{} not found", _0)]
+    NotFound(#[error(not(source))] &'static str),
+
+    #[display(fmt = "Conflict : {}", _0)]
+    Conflict(#[error(not(source))] String),
+
     #[display(fmt = "Database error")]
     Database(DbErr),
 
@@ -21,11 +30,27 @@ impl RusError {
     pub fn name(&self) -> String {
         match self {
             Self::Forbidden => "Forbidden".to_string(),
+            Self::Unauthorized => "Unauthorized".to_string(),
+            Self::NotFound(what) => format!("Not found : {0}", what),
+            Self::Conflict(details) => format!("Conflict : {0}", details),
             Self::Unknown => "Unknown".to_string(),
             Self::Database(details) => format!("Database : {0}", details),
             Self::Redis(details) => format!("Redis : {0}", details),
         }
     }
+
+    /// Stable, machine-readable identifier of the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Forbidden => "forbidden",
+            Self::Unauthorized => "unauthorized",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Database(_) => "database_error",
+            Self::Redis(_) => "cache_error",
+            Self::Unknown => "unknown_error",
+        }
+    }
 }
 
 impl From<DbErr> for RusError {
@@ -0,0 +1,47 @@
+use chrono::Utc;
+use sea_orm::*;
+
+use ::entity::redirection;
+use ::entity::{redirection_version, redirection_version::Entity as RedirectionVersion};
+
+/// Number of the version following the last recorded one
+pub(crate) async fn next_version<C: ConnectionTrait>(
+    db: &C,
+    redirection_id: i32,
+) -> Result<i32, DbErr> {
+    let last = RedirectionVersion::find()
+        .filter(redirection_version::Column::RedirectionId.eq(redirection_id))
+        .order_by_desc(redirection_version::Column::Version)
+        .one(db)
+        .await?;
+    Ok(last.map_or(1, |last| last.version + 1))
+}
+
+/// Records the current destination of the redirection as the given version
+pub(crate) async fn record<C: ConnectionTrait>(
+    db: &C,
+    redirection: &redirection::Model,
+    version: i32,
+) -> Result<redirection_version::Model, DbErr> {
+    redirection_version::ActiveModel {
+        redirection_id: Set(redirection.id),
+        version: Set(version),
+        long_url: Set(redirection.long_url.to_owned()),
+        created_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+}
+
+/// Forgets the versions of purged redirections
+pub(crate) async fn forget<C: ConnectionTrait>(
+    db: &C,
+    redirection_ids: impl IntoIterator<Item = i32>,
+) -> Result<(), DbErr> {
+    RedirectionVersion::delete_many()
+        .filter(redirection_version::Column::RedirectionId.is_in(redirection_ids))
+        .exec(db)
+        .await?;
+    Ok(())
+}
@@ -1,13 +1,18 @@
+mod audit;
 mod cache;
 pub mod errors;
+mod history;
+mod metrics;
 mod mutation;
 mod query;
 
+pub use audit::{Actor, AuditAction, AuditFilter};
 pub use cache::*;
 pub use mutation::*;
 pub use query::*;
 
 pub use chrono;
 pub use derive_more;
+pub use prometheus;
 pub use redis;
 pub use sea_orm;
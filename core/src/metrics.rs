@@ -0,0 +1,18 @@
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+
+lazy_static! {
+    /// The hit ratio of a backend is its share of `hit` lookups
+    pub(crate) static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "rus_cache_lookups_total",
+        "Cache lookups, by backend and result",
+        &["backend", "result"]
+    )
+    .expect("The cache metrics are registered once");
+    /// 1 while redis is unavailable and the cache degraded to its fallback
+    pub(crate) static ref CACHE_DEGRADED: IntGauge = register_int_gauge!(
+        "rus_cache_degraded",
+        "Whether redis is unavailable and the cache degraded to its fallback"
+    )
+    .expect("The cache metrics are registered once");
+}
@@ -1,10 +1,15 @@
-use crate::Query;
+use crate::audit::{self, Actor, AuditAction};
+use crate::errors::RusError;
+use crate::{history, Cache, Query};
 use ::entity::{redirection, redirection::Entity as Redirection};
 use chrono::{Duration, NaiveDateTime, Utc};
 use rand::{distributions::Alphanumeric, Rng};
 use sea_orm::*;
+use tracing::{instrument, warn};
 
 const SHORT_URL_LENGTH: usize = 6;
+/// Short urls drawn before giving up, running out of them means the space is nearly exhausted
+const MAX_SHORT_URL_ATTEMPTS: usize = 16;
 
 pub struct Mutation;
 
@@ -13,19 +18,18 @@ pub struct CreateMutation {
     short_url: String,
     ip_address: String,
     expiration_date: NaiveDateTime,
+    title: Option<String>,
+    notes: Option<String>,
 }
 
 pub struct UpdateMutation {
-    short_url: String,
+    id: i32,
     long_url: String,
 }
 
 impl UpdateMutation {
-    pub fn new(short_url: String, long_url: String) -> UpdateMutation {
-        UpdateMutation {
-            short_url,
-            long_url,
-        }
+    pub fn new(id: i32, long_url: String) -> UpdateMutation {
+        UpdateMutation { id, long_url }
     }
 }
 
@@ -45,65 +49,333 @@ impl CreateMutation {
             ip_address,
             short_url: generate_random_string(SHORT_URL_LENGTH),
             expiration_date,
+            title: None,
+            notes: None,
         }
     }
 
+    /// Optional free text describing the link, used when searching
+    pub fn with_details(mut self, title: Option<String>, notes: Option<String>) -> CreateMutation {
+        self.title = title;
+        self.notes = notes;
+        self
+    }
+
     fn regenerate_short_url(&mut self) {
         self.short_url = generate_random_string(SHORT_URL_LENGTH);
     }
 }
 
+/// Every mutation is recorded in the audit trail by the same transaction, the cache is only
+/// invalidated once the transaction is committed
 impl Mutation {
+    #[instrument(skip_all)]
     pub async fn create_redirection(
         db: &DbConn,
+        cache: &dyn Cache,
+        actor: &Actor,
         mut create: CreateMutation,
-    ) -> Result<redirection::ActiveModel, DbErr> {
-        loop {
-            let existing =
-                Query::find_redirection_by_short_url(db, create.short_url.to_string()).await?;
-            if existing.is_none() {
-                return redirection::ActiveModel {
-                    long_url: Set(create.long_url),
-                    short_url: Set(create.short_url),
-                    ip_address: Set(create.ip_address),
-                    expiration_date: Set(Some(create.expiration_date)),
-                    ..Default::default()
-                }
-                .save(db)
-                .await;
+    ) -> Result<redirection::ActiveModel, RusError> {
+        let txn = db.begin().await?;
+        let mut attempts = 1;
+        // trashed redirections keep their short url until they are purged
+        while Query::find_short_url_owner(&txn, create.short_url.to_string())
+            .await?
+            .is_some()
+        {
+            if attempts == MAX_SHORT_URL_ATTEMPTS {
+                return Err(RusError::Conflict(
+                    "no free short url could be generated".to_owned(),
+                ));
             }
+            attempts += 1;
             create.regenerate_short_url();
         }
+        let created = redirection::ActiveModel {
+            long_url: Set(create.long_url),
+            short_url: Set(create.short_url),
+            ip_address: Set(create.ip_address),
+            expiration_date: Set(Some(create.expiration_date)),
+            title: Set(create.title),
+            notes: Set(create.notes),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+        history::record(&txn, &created, 1).await?;
+        audit::record(&txn, AuditAction::Create, actor, None, Some(&created)).await?;
+        txn.commit().await?;
+
+        // the short url may have been cached as missing
+        invalidate(cache, &created.short_url).await;
+        Ok(created.into_active_model())
     }
 
+    #[instrument(skip_all, fields(id = update.id))]
     pub async fn update_redirection_by_id(
         db: &DbConn,
+        cache: &dyn Cache,
+        actor: &Actor,
         update: UpdateMutation,
-    ) -> Result<redirection::Model, DbErr> {
-        let found = Query::find_redirection_by_short_url(db, update.short_url)
+    ) -> Result<redirection::Model, RusError> {
+        let txn = db.begin().await?;
+        let found = Query::find_redirection_by_id(&txn, update.id)
             .await?
-            .ok_or_else(|| DbErr::Custom("Cannot find redirection.".to_owned()))?;
+            .ok_or(RusError::NotFound("Redirection"))?;
 
-        redirection::ActiveModel {
+        let updated = redirection::ActiveModel {
             id: Set(found.id),
             long_url: Set(update.long_url),
             ..Default::default()
         }
-        .update(db)
-        .await
+        .update(&txn)
+        .await?;
+        let version = history::next_version(&txn, updated.id).await?;
+        history::record(&txn, &updated, version).await?;
+        audit::record(
+            &txn,
+            AuditAction::Update,
+            actor,
+            Some(&found),
+            Some(&updated),
+        )
+        .await?;
+        txn.commit().await?;
+
+        invalidate(cache, &updated.short_url).await;
+        Ok(updated)
+    }
+
+    /// Brings back the destination of a previous version. The history is never rewritten, the
+    /// rollback is recorded as a new version
+    #[instrument(skip(db, cache, actor))]
+    pub async fn rollback_redirection(
+        db: &DbConn,
+        cache: &dyn Cache,
+        actor: &Actor,
+        id: i32,
+        version: i32,
+    ) -> Result<redirection::Model, RusError> {
+        let txn = db.begin().await?;
+        let found = Query::find_redirection_by_id(&txn, id)
+            .await?
+            .ok_or(RusError::NotFound("Redirection"))?;
+        let previous = Query::find_redirection_version(&txn, found.id, version)
+            .await?
+            .ok_or(RusError::NotFound("Version"))?;
+
+        let rolled_back = redirection::ActiveModel {
+            id: Set(found.id),
+            long_url: Set(previous.long_url),
+            ..Default::default()
+        }
+        .update(&txn)
+        .await?;
+        let version = history::next_version(&txn, rolled_back.id).await?;
+        history::record(&txn, &rolled_back, version).await?;
+        audit::record(
+            &txn,
+            AuditAction::Rollback,
+            actor,
+            Some(&found),
+            Some(&rolled_back),
+        )
+        .await?;
+        txn.commit().await?;
+
+        invalidate(cache, &rolled_back.short_url).await;
+        Ok(rolled_back)
+    }
+
+    /// Moves the redirection to the trash, it is no longer served but can be restored
+    #[instrument(skip(db, cache, actor))]
+    pub async fn delete_redirection(
+        db: &DbConn,
+        cache: &dyn Cache,
+        actor: &Actor,
+        id: i32,
+    ) -> Result<redirection::Model, RusError> {
+        let txn = db.begin().await?;
+        let found = Query::find_redirection_by_id(&txn, id)
+            .await?
+            .ok_or(RusError::NotFound("Redirection"))?;
+
+        let trashed = trash(&txn, found.id).await?;
+        audit::record(
+            &txn,
+            AuditAction::Delete,
+            actor,
+            Some(&found),
+            Some(&trashed),
+        )
+        .await?;
+        txn.commit().await?;
+
+        invalidate(cache, &trashed.short_url).await;
+        Ok(trashed)
+    }
+
+    /// Moves every redirection to the trash, returns their number
+    #[instrument(skip_all)]
+    pub async fn delete_all_redirections(
+        db: &DbConn,
+        cache: &dyn Cache,
+        actor: &Actor,
+    ) -> Result<u64, RusError> {
+        let txn = db.begin().await?;
+        let redirections = Query::find_all(&txn).await?;
+        for redirection in &redirections {
+            let trashed = trash(&txn, redirection.id).await?;
+            audit::record(
+                &txn,
+                AuditAction::Delete,
+                actor,
+                Some(redirection),
+                Some(&trashed),
+            )
+            .await?;
+        }
+        txn.commit().await?;
+
+        for redirection in &redirections {
+            invalidate(cache, &redirection.short_url).await;
+        }
+        Ok(redirections.len() as u64)
+    }
+
+    /// Moves the redirections whose expiration date is reached to the trash, and returns them
+    #[instrument(skip_all)]
+    pub async fn remove_expired_redirections(
+        db: &DbConn,
+        cache: &dyn Cache,
+    ) -> Result<Vec<redirection::Model>, RusError> {
+        let txn = db.begin().await?;
+        let expired = Query::trash_outdated_redirections(&txn).await?;
+        for trashed in &expired {
+            let before = redirection::Model {
+                deleted_at: None,
+                ..trashed.clone()
+            };
+            audit::record(
+                &txn,
+                AuditAction::Expire,
+                &Actor::System,
+                Some(&before),
+                Some(trashed),
+            )
+            .await?;
+        }
+        txn.commit().await?;
+
+        for redirection in &expired {
+            invalidate(cache, &redirection.short_url).await;
+        }
+        Ok(expired)
+    }
+
+    /// Takes the redirection out of the trash. An expired redirection is given a new lifetime,
+    /// or it would be trashed again right away
+    #[instrument(skip(db, cache, actor))]
+    pub async fn restore_redirection(
+        db: &DbConn,
+        cache: &dyn Cache,
+        actor: &Actor,
+        id: i32,
+        link_lifetime: Duration,
+    ) -> Result<redirection::Model, RusError> {
+        let txn = db.begin().await?;
+        let found = match Query::find_trashed_redirection_by_id(&txn, id).await? {
+            Some(found) => found,
+            // restored in the meantime
+            None if Query::find_redirection_by_id(&txn, id).await?.is_some() => {
+                return Err(RusError::Conflict(
+                    "the redirection is not in the trash".to_owned(),
+                ))
+            }
+            None => return Err(RusError::NotFound("Redirection")),
+        };
+
+        let now = Utc::now().naive_utc();
+        let mut restored = redirection::ActiveModel {
+            id: Set(found.id),
+            deleted_at: Set(None),
+            ..Default::default()
+        };
+        if matches!(found.expiration_date, Some(expiration) if expiration <= now) {
+            restored.expiration_date = Set(Some(now + link_lifetime));
+        }
+        let restored = restored.update(&txn).await?;
+        audit::record(
+            &txn,
+            AuditAction::Restore,
+            actor,
+            Some(&found),
+            Some(&restored),
+        )
+        .await?;
+        txn.commit().await?;
+
+        // the short url may have been cached as missing
+        invalidate(cache, &restored.short_url).await;
+        Ok(restored)
     }
 
-    pub async fn delete_redirection(db: &DbConn, id: i32) -> Result<DeleteResult, DbErr> {
-        let redirection: redirection::ActiveModel = Redirection::find_by_id(id)
-            .one(db)
+    /// Permanently deletes a redirection from the trash along with its history, which frees its
+    /// short url
+    #[instrument(skip(db, actor))]
+    pub async fn purge_redirection(
+        db: &DbConn,
+        actor: &Actor,
+        id: i32,
+    ) -> Result<redirection::Model, RusError> {
+        let txn = db.begin().await?;
+        let found = Query::find_trashed_redirection_by_id(&txn, id)
             .await?
-            .ok_or_else(|| DbErr::Custom("Cannot find redirection.".to_owned()))
-            .map(Into::into)?;
+            .ok_or(RusError::NotFound("Redirection"))?;
+
+        Redirection::delete_by_id(found.id).exec(&txn).await?;
+        history::forget(&txn, [found.id]).await?;
+        audit::record(&txn, AuditAction::Purge, actor, Some(&found), None).await?;
+        txn.commit().await?;
+        Ok(found)
+    }
 
-        redirection.delete(db).await
+    /// Permanently deletes the redirections trashed before the given date, returns their number
+    #[instrument(skip(db, actor))]
+    pub async fn purge_trash(
+        db: &DbConn,
+        actor: &Actor,
+        deleted_before: NaiveDateTime,
+    ) -> Result<u64, RusError> {
+        let txn = db.begin().await?;
+        let purged = Query::purge_trash(&txn, deleted_before).await?;
+        history::forget(&txn, purged.iter().map(|redirection| redirection.id)).await?;
+        for redirection in &purged {
+            audit::record(&txn, AuditAction::Purge, actor, Some(redirection), None).await?;
+        }
+        txn.commit().await?;
+        Ok(purged.len() as u64)
     }
+}
 
-    pub async fn delete_all_redirections(db: &DbConn) -> Result<DeleteResult, DbErr> {
-        Redirection::delete_many().exec(db).await
+async fn trash<C: ConnectionTrait>(db: &C, id: i32) -> Result<redirection::Model, DbErr> {
+    redirection::ActiveModel {
+        id: Set(id),
+        deleted_at: Set(Some(Utc::now().naive_utc())),
+        ..Default::default()
+    }
+    .update(db)
+    .await
+}
+
+/// The database is the source of truth: once it has been modified, failing to
+/// invalidate the cache must not fail the whole mutation
+async fn invalidate(cache: &dyn Cache, short_url: &str) {
+    if let Err(err) = cache.remove(short_url).await {
+        warn!(
+            "Failed to invalidate cache entry of {}, cause : {}",
+            short_url,
+            err.name()
+        );
     }
 }
@@ -1,65 +1,609 @@
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use sea_orm::sea_query::{BinOper, Expr, Func, LikeExpr, SimpleExpr};
 use sea_orm::*;
+use serde::Deserialize;
+use tracing::instrument;
+use utoipa::ToSchema;
 
+use crate::AuditFilter;
+use ::entity::{audit_event, audit_event::Entity as AuditEvent};
 use ::entity::{redirection, redirection::Entity as Redirection};
+use ::entity::{redirection_version, redirection_version::Entity as RedirectionVersion};
 
 pub struct Query;
 
+/// Column the redirections can be sorted by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Id,
+    Creation,
+    Expiration,
+    LastAccess,
+    Clicks,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    Active,
+    Expired,
+}
+
+/// Criteria used to narrow down a listing of redirections, every criterion is optional
+#[derive(Debug, Clone, Default)]
+pub struct RedirectionFilter {
+    pub domain: Option<String>,
+    pub created_after: Option<NaiveDateTime>,
+    pub created_before: Option<NaiveDateTime>,
+    pub state: Option<LinkState>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub filter: RedirectionFilter,
+    pub sort: SortField,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CursorValue {
+    Date(NaiveDateTime),
+    Count(i64),
+}
+
+/// Position of a redirection in a sorted listing, used for keyset pagination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    value: CursorValue,
+    id: i32,
+}
+
+const CURSOR_SEPARATOR: char = '~';
+const CURSOR_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// Links without an expiration date are sorted as if they expired last
+fn never_expires() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(9999, 12, 31)
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .expect("9999-12-31 23:59:59 is a valid date")
+}
+
+impl Cursor {
+    fn from_model(sort: SortField, model: &redirection::Model) -> Cursor {
+        let value = match sort {
+            SortField::Id => CursorValue::Count(model.id.into()),
+            SortField::Creation => CursorValue::Date(model.creation_date),
+            SortField::Expiration => {
+                CursorValue::Date(model.expiration_date.unwrap_or_else(never_expires))
+            }
+            SortField::LastAccess => CursorValue::Date(model.last_access_date),
+            SortField::Clicks => CursorValue::Count(model.clicks),
+        };
+        Cursor {
+            value,
+            id: model.id,
+        }
+    }
+
+    /// Reads a cursor previously produced by `Cursor::encode` for the same sort field
+    pub fn decode(sort: SortField, encoded: &str) -> Option<Cursor> {
+        let (value, id) = encoded.rsplit_once(CURSOR_SEPARATOR)?;
+        let id = id.parse::<i32>().ok()?;
+        let value = match sort {
+            SortField::Id | SortField::Clicks => CursorValue::Count(value.parse().ok()?),
+            SortField::Creation | SortField::Expiration | SortField::LastAccess => {
+                CursorValue::Date(NaiveDateTime::parse_from_str(value, CURSOR_DATE_FORMAT).ok()?)
+            }
+        };
+        Some(Cursor { value, id })
+    }
+
+    pub fn encode(&self) -> String {
+        let value = match self.value {
+            CursorValue::Date(date) => date.format(CURSOR_DATE_FORMAT).to_string(),
+            CursorValue::Count(count) => count.to_string(),
+        };
+        format!("{}{}{}", value, CURSOR_SEPARATOR, self.id)
+    }
+
+    fn value_expr(&self) -> SimpleExpr {
+        match self.value {
+            CursorValue::Date(date) => Expr::val(date).into(),
+            CursorValue::Count(count) => Expr::val(count).into(),
+        }
+    }
+}
+
+impl SortField {
+    fn expr(&self) -> SimpleExpr {
+        match self {
+            SortField::Id => Expr::col(redirection::Column::Id).into(),
+            SortField::Creation => Expr::col(redirection::Column::CreationDate).into(),
+            SortField::Expiration => Func::coalesce([
+                SimpleExpr::from(Expr::col(redirection::Column::ExpirationDate)),
+                SimpleExpr::from(Expr::val(never_expires())),
+            ]),
+            SortField::LastAccess => Expr::col(redirection::Column::LastAccessDate).into(),
+            SortField::Clicks => Expr::col(redirection::Column::Clicks).into(),
+        }
+    }
+}
+
+impl From<SortOrder> for Order {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Asc => Order::Asc,
+            SortOrder::Desc => Order::Desc,
+        }
+    }
+}
+
+/// Redirections that are not in the trash
+fn live() -> Condition {
+    Condition::all().add(redirection::Column::DeletedAt.is_null())
+}
+
+/// Escape character of the `LIKE` patterns, unlike `\` it is quoted the same way by every backend
+const LIKE_ESCAPE: char = '!';
+
+/// Escapes the wildcards of a value matched literally by `LIKE`
+fn escape_like(value: &str) -> String {
+    value.chars().fold(String::new(), |mut escaped, c| {
+        if matches!(c, LIKE_ESCAPE | '%' | '_') {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+fn like(pattern: String) -> LikeExpr {
+    LikeExpr::new(pattern).escape(LIKE_ESCAPE)
+}
+
+/// Columns searched by the `LIKE` fallback of the full-text search
+const SEARCHED_COLUMNS: [&str; 4] = [
+    "long_url",
+    "short_url",
+    "COALESCE(title, '')",
+    "COALESCE(notes, '')",
+];
+
+/// Number of columns containing the term, used to rank the results of the `LIKE` fallback
+fn matches_count(term: &str) -> SimpleExpr {
+    let sql = SEARCHED_COLUMNS
+        .iter()
+        .map(|column| {
+            format!(
+                "CASE WHEN LOWER({}) LIKE ? ESCAPE '{}' THEN 1 ELSE 0 END",
+                column, LIKE_ESCAPE
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" + ");
+    let pattern = format!("%{}%", escape_like(&term.to_lowercase()));
+    Expr::cust_with_values(
+        &format!("({})", sql),
+        SEARCHED_COLUMNS.iter().map(|_| pattern.to_owned()),
+    )
+}
+
+/// Lowercased destination, starting right after the separator of its scheme. Only the first
+/// `://` is considered, the ones found in the path or the query can't fake the host
+fn authority_expr(backend: DbBackend) -> SimpleExpr {
+    let position = match backend {
+        DbBackend::Postgres => "STRPOS",
+        DbBackend::MySql | DbBackend::Sqlite => "INSTR",
+    };
+    Expr::cust(&format!(
+        "LOWER(SUBSTR(long_url, {}(long_url, '://') + 3))",
+        position
+    ))
+}
+
+impl RedirectionFilter {
+    fn condition(&self, backend: DbBackend) -> Condition {
+        let mut condition = live();
+
+        if let Some(domain) = &self.domain {
+            let host = escape_like(&domain.trim().to_lowercase());
+            let authority = || Expr::expr(authority_expr(backend));
+            condition = condition
+                .add(redirection::Column::LongUrl.contains("://"))
+                .add(
+                    Condition::any()
+                        .add(authority().like(like(host.to_owned())))
+                        .add(authority().like(like(format!("{}/%", host))))
+                        .add(authority().like(like(format!("{}:%", host))))
+                        .add(authority().like(like(format!("{}?%", host))))
+                        .add(authority().like(like(format!("{}#%", host)))),
+                );
+        }
+        if let Some(after) = self.created_after {
+            condition = condition.add(redirection::Column::CreationDate.gte(after));
+        }
+        if let Some(before) = self.created_before {
+            condition = condition.add(redirection::Column::CreationDate.lt(before));
+        }
+        if let Some(state) = self.state {
+            let now = Utc::now().naive_utc();
+            condition = condition.add(match state {
+                LinkState::Expired => {
+                    Condition::all().add(redirection::Column::ExpirationDate.lte(now))
+                }
+                LinkState::Active => Condition::any()
+                    .add(redirection::Column::ExpirationDate.is_null())
+                    .add(redirection::Column::ExpirationDate.gt(now)),
+            });
+        }
+        if let Some(ip_address) = &self.ip_address {
+            condition = condition.add(redirection::Column::IpAddress.eq(ip_address.as_str()));
+        }
+
+        condition
+    }
+}
+
+impl ListOptions {
+    fn select(&self, backend: DbBackend) -> Select<Redirection> {
+        let order = Order::from(self.order);
+        let select = Redirection::find()
+            .filter(self.filter.condition(backend))
+            .order_by(self.sort.expr(), order.clone());
+
+        if self.sort == SortField::Id {
+            select
+        } else {
+            select.order_by(redirection::Column::Id, order)
+        }
+    }
+
+    /// Only keeps the redirections located after the cursor in the requested ordering
+    fn after(&self, cursor: &Cursor) -> Condition {
+        let sort = Expr::expr(self.sort.expr());
+        let id = Expr::col(redirection::Column::Id);
+        let (value, last_id) = (cursor.value_expr(), cursor.id);
+
+        match self.order {
+            SortOrder::Asc => Condition::any()
+                .add(sort.clone().binary(BinOper::GreaterThan, value.clone()))
+                .add(
+                    Condition::all()
+                        .add(sort.binary(BinOper::Equal, value))
+                        .add(id.gt(last_id)),
+                ),
+            SortOrder::Desc => Condition::any()
+                .add(sort.clone().binary(BinOper::SmallerThan, value.clone()))
+                .add(
+                    Condition::all()
+                        .add(sort.binary(BinOper::Equal, value))
+                        .add(id.lt(last_id)),
+                ),
+        }
+    }
+}
+
 impl Query {
-    pub async fn find_redirection_by_id(
-        db: &DbConn,
+    #[instrument(skip(db))]
+    pub async fn find_redirection_by_id<C: ConnectionTrait>(
+        db: &C,
         id: i32,
     ) -> Result<Option<redirection::Model>, DbErr> {
-        Redirection::find_by_id(id).one(db).await
+        Redirection::find_by_id(id).filter(live()).one(db).await
     }
 
-    pub async fn find_redirection_by_short_url(
-        db: &DbConn,
+    #[instrument(skip(db))]
+    pub async fn find_redirection_by_short_url<C: ConnectionTrait>(
+        db: &C,
         short_url: String,
     ) -> Result<Option<redirection::Model>, DbErr> {
         Redirection::find()
             .filter(redirection::Column::ShortUrl.eq(short_url))
+            .filter(live())
             .one(db)
             .await
     }
 
-    pub async fn delete_outdated_redirections(
-        db: &DbConn,
+    /// Redirection holding the short url, even when it is in the trash
+    #[instrument(skip(db))]
+    pub async fn find_short_url_owner<C: ConnectionTrait>(
+        db: &C,
+        short_url: String,
+    ) -> Result<Option<redirection::Model>, DbErr> {
+        Redirection::find()
+            .filter(redirection::Column::ShortUrl.eq(short_url))
+            .one(db)
+            .await
+    }
+
+    #[instrument(skip(db))]
+    pub async fn find_trashed_redirection_by_id<C: ConnectionTrait>(
+        db: &C,
+        id: i32,
+    ) -> Result<Option<redirection::Model>, DbErr> {
+        Redirection::find_by_id(id)
+            .filter(redirection::Column::DeletedAt.is_not_null())
+            .one(db)
+            .await
+    }
+
+    /// Moves the redirections whose expiration date is reached to the trash, and returns them.
+    /// Not every backend supports `UPDATE ... RETURNING`, so they are selected first
+    #[instrument(skip(db))]
+    pub async fn trash_outdated_redirections<C: ConnectionTrait>(
+        db: &C,
+    ) -> Result<Vec<redirection::Model>, DbErr> {
+        let now = Utc::now().naive_utc();
+        let expired = Redirection::find()
+            .filter(redirection::Column::ExpirationDate.lte(now))
+            .filter(live())
+            .all(db)
+            .await?;
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+
+        Redirection::update_many()
+            .col_expr(redirection::Column::DeletedAt, Expr::value(now))
+            .filter(redirection::Column::Id.is_in(expired.iter().map(|model| model.id)))
+            .filter(live())
+            .exec(db)
+            .await?;
+        Ok(expired
+            .into_iter()
+            .map(|model| redirection::Model {
+                deleted_at: Some(now),
+                ..model
+            })
+            .collect())
+    }
+
+    /// Permanently deletes the redirections trashed before the given date, and returns them
+    #[instrument(skip(db))]
+    pub async fn purge_trash<C: ConnectionTrait>(
+        db: &C,
+        deleted_before: NaiveDateTime,
     ) -> Result<Vec<redirection::Model>, DbErr> {
-        let stmt = Statement::from_string(
-            DbBackend::Postgres,
-            "DELETE FROM redirection WHERE expiration_date <= CURRENT_TIMESTAMP RETURNING *;"
-                .to_owned(),
-        );
-        let exec_result = Redirection::find().from_raw_sql(stmt).all(db);
-        exec_result.await
+        let purged = Redirection::find()
+            .filter(redirection::Column::DeletedAt.lte(deleted_before))
+            .all(db)
+            .await?;
+        if purged.is_empty() {
+            return Ok(purged);
+        }
+
+        Redirection::delete_many()
+            .filter(redirection::Column::Id.is_in(purged.iter().map(|model| model.id)))
+            .exec(db)
+            .await?;
+        Ok(purged)
+    }
+
+    /// Returns the requested page of the trash, the most recently deleted first,
+    /// and the number of pages
+    #[instrument(skip(db))]
+    pub async fn find_trash_in_page(
+        db: &DbConn,
+        page: u64,
+        redirections_per_page: u64,
+    ) -> Result<(Vec<redirection::Model>, u64), DbErr> {
+        let paginator = Redirection::find()
+            .filter(redirection::Column::DeletedAt.is_not_null())
+            .order_by_desc(redirection::Column::DeletedAt)
+            .order_by_desc(redirection::Column::Id)
+            .paginate(db, redirections_per_page);
+        let num_pages = paginator.num_pages().await?;
+        paginator.fetch_page(page - 1).await.map(|p| (p, num_pages))
     }
 
-    pub async fn find_all(db: &DbConn) -> Result<Vec<redirection::Model>, DbErr> {
-        Redirection::find().all(db).await
+    #[instrument(skip(db))]
+    pub async fn find_all<C: ConnectionTrait>(db: &C) -> Result<Vec<redirection::Model>, DbErr> {
+        Redirection::find().filter(live()).all(db).await
     }
 
+    #[instrument(skip(db))]
     pub async fn update_access_date(db: &DbConn, short_url: String) -> Result<bool, DbErr> {
-        let res = Statement::from_sql_and_values(
-            DbBackend::Postgres,
-            r#"UPDATE redirection set last_access_date = CURRENT_TIMESTAMP WHERE short_url = $1"#,
-            vec![short_url.into()],
-        );
-        let exec_result = db.execute(res).await?;
-        Ok(exec_result.rows_affected() == 1)
+        let exec_result = Redirection::update_many()
+            .col_expr(
+                redirection::Column::LastAccessDate,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .col_expr(
+                redirection::Column::Clicks,
+                Expr::col(redirection::Column::Clicks).add(1),
+            )
+            .filter(redirection::Column::ShortUrl.eq(short_url))
+            .filter(live())
+            .exec(db)
+            .await?;
+        Ok(exec_result.rows_affected == 1)
+    }
+
+    /// Active redirections, the most recently accessed first
+    #[instrument(skip(db))]
+    pub async fn find_recently_accessed(
+        db: &DbConn,
+        limit: u64,
+    ) -> Result<Vec<redirection::Model>, DbErr> {
+        let filter = RedirectionFilter {
+            state: Some(LinkState::Active),
+            ..Default::default()
+        };
+        Redirection::find()
+            .filter(filter.condition(db.get_database_backend()))
+            .order_by_desc(redirection::Column::LastAccessDate)
+            .limit(limit)
+            .all(db)
+            .await
     }
 
+    /// Returns the requested page, the number of pages and the cursor of the next page, if any
+    #[instrument(skip(db))]
     pub async fn find_redirections_in_page(
         db: &DbConn,
+        options: &ListOptions,
         page: u64,
         redirections_per_page: u64,
-    ) -> Result<(Vec<redirection::Model>, u64), DbErr> {
+    ) -> Result<(Vec<redirection::Model>, u64, Option<Cursor>), DbErr> {
         // Setup paginator
-        let paginator = Redirection::find()
-            .order_by_asc(redirection::Column::Id)
+        let paginator = options
+            .select(db.get_database_backend())
             .paginate(db, redirections_per_page);
         let num_pages = paginator.num_pages().await?;
 
         // Fetch paginated posts
+        let redirections = paginator.fetch_page(page - 1).await?;
+        let next = if page < num_pages {
+            redirections
+                .last()
+                .map(|last| Cursor::from_model(options.sort, last))
+        } else {
+            None
+        };
+        Ok((redirections, num_pages, next))
+    }
+
+    /// Returns the requested page of the audit trail, the most recent events first,
+    /// and the number of pages
+    #[instrument(skip(db))]
+    pub async fn find_audit_events_in_page(
+        db: &DbConn,
+        filter: &AuditFilter,
+        page: u64,
+        events_per_page: u64,
+    ) -> Result<(Vec<audit_event::Model>, u64), DbErr> {
+        let paginator = AuditEvent::find()
+            .filter(filter.condition())
+            .order_by_desc(audit_event::Column::CreatedAt)
+            .order_by_desc(audit_event::Column::Id)
+            .paginate(db, events_per_page);
+        let num_pages = paginator.num_pages().await?;
         paginator.fetch_page(page - 1).await.map(|p| (p, num_pages))
     }
+
+    /// Number of redirections currently served: neither trashed nor expired
+    #[instrument(skip(db))]
+    pub async fn count_live_redirections(db: &DbConn) -> Result<u64, DbErr> {
+        Redirection::find()
+            .filter(live())
+            .filter(
+                Condition::any()
+                    .add(redirection::Column::ExpirationDate.is_null())
+                    .add(redirection::Column::ExpirationDate.gt(Utc::now().naive_utc())),
+            )
+            .count(db)
+            .await
+    }
+
+    /// Returns the destinations the redirection went through, the most recent first
+    #[instrument(skip(db))]
+    pub async fn find_redirection_history(
+        db: &DbConn,
+        redirection_id: i32,
+    ) -> Result<Vec<redirection_version::Model>, DbErr> {
+        RedirectionVersion::find()
+            .filter(redirection_version::Column::RedirectionId.eq(redirection_id))
+            .order_by_desc(redirection_version::Column::Version)
+            .all(db)
+            .await
+    }
+
+    #[instrument(skip(db))]
+    pub async fn find_redirection_version<C: ConnectionTrait>(
+        db: &C,
+        redirection_id: i32,
+        version: i32,
+    ) -> Result<Option<redirection_version::Model>, DbErr> {
+        RedirectionVersion::find()
+            .filter(redirection_version::Column::RedirectionId.eq(redirection_id))
+            .filter(redirection_version::Column::Version.eq(version))
+            .one(db)
+            .await
+    }
+
+    /// Ranked full-text search over the destination, short url, title and notes of the links.
+    /// Postgres uses the indexed `search_vector` column. Other backends fall back to `LIKE`: every
+    /// term must be found in one of the fields, and the links are ranked by the number of fields
+    /// matching each term
+    #[instrument(skip(db))]
+    pub async fn search_redirections(
+        db: &DbConn,
+        terms: &str,
+        page: u64,
+        redirections_per_page: u64,
+    ) -> Result<(Vec<redirection::Model>, u64), DbErr> {
+        let select = match db.get_database_backend() {
+            DbBackend::Postgres => Redirection::find()
+                .filter(live())
+                .filter(Expr::cust_with_values(
+                    "search_vector @@ websearch_to_tsquery('simple', ?)",
+                    vec![terms],
+                ))
+                .order_by(
+                    Expr::cust_with_values(
+                        "ts_rank(search_vector, websearch_to_tsquery('simple', ?))",
+                        vec![terms],
+                    ),
+                    Order::Desc,
+                )
+                .order_by_desc(redirection::Column::Id),
+            _ => {
+                let terms: Vec<&str> = terms.split_whitespace().collect();
+                let mut condition = live();
+                for term in &terms {
+                    condition = condition.add(Expr::expr(matches_count(term)).gt(0));
+                }
+                let rank = terms
+                    .iter()
+                    .map(|term| matches_count(term))
+                    .reduce(|rank, count| rank.add(count))
+                    .unwrap_or_else(|| Expr::val(0).into());
+                Redirection::find()
+                    .filter(condition)
+                    .order_by(rank, Order::Desc)
+                    .order_by_desc(redirection::Column::Id)
+            }
+        };
+
+        let paginator = select.paginate(db, redirections_per_page);
+        let num_pages = paginator.num_pages().await?;
+        paginator.fetch_page(page - 1).await.map(|p| (p, num_pages))
+    }
+
+    /// Keyset pagination: returns up to `limit` redirections following `after`,
+    /// and the cursor of the next batch when there are more
+    #[instrument(skip(db))]
+    pub async fn find_redirections_after(
+        db: &DbConn,
+        options: &ListOptions,
+        after: Option<&Cursor>,
+        limit: u64,
+    ) -> Result<(Vec<redirection::Model>, Option<Cursor>), DbErr> {
+        let mut select = options.select(db.get_database_backend());
+        if let Some(cursor) = after {
+            select = select.filter(options.after(cursor));
+        }
+
+        let mut redirections = select.limit(limit + 1).all(db).await?;
+        let next = if redirections.len() as u64 > limit {
+            redirections.truncate(limit as usize);
+            redirections
+                .last()
+                .map(|last| Cursor::from_model(options.sort, last))
+        } else {
+            None
+        };
+        Ok((redirections, next))
+    }
 }
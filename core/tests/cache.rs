@@ -0,0 +1,205 @@
+use std::num::NonZeroUsize;
+
+use chrono::{Duration, Utc};
+use rus_core::{Cache, CachedRedirection, InMemoryCache};
+
+fn in_memory(max_entries: usize, max_bytes: usize) -> InMemoryCache {
+    InMemoryCache::new(NonZeroUsize::new(max_entries).unwrap(), max_bytes)
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used_entry() {
+    let cache = in_memory(2, 1024);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_entry("b".into(), "https://b.com".into(), expires)
+        .await
+        .unwrap();
+    assert_eq!(
+        cache.try_get("a").await,
+        Some(CachedRedirection::Found("https://a.com".to_owned()))
+    );
+    cache
+        .add_entry("c".into(), "https://c.com".into(), expires)
+        .await
+        .unwrap();
+
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.try_get("b").await, None);
+    assert_eq!(
+        cache.try_get("a").await,
+        Some(CachedRedirection::Found("https://a.com".to_owned()))
+    );
+    assert_eq!(
+        cache.try_get("c").await,
+        Some(CachedRedirection::Found("https://c.com".to_owned()))
+    );
+}
+
+#[tokio::test]
+async fn stays_within_max_bytes() {
+    let cache = in_memory(100, 30);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_entry("b".into(), "https://b.com".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_entry("c".into(), "https://c.com".into(), expires)
+        .await
+        .unwrap();
+
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.used_bytes(), 28);
+    assert_eq!(cache.try_get("a").await, None);
+
+    // replacing an entry doesn't count its size twice
+    cache
+        .add_entry("c".into(), "https://c.org".into(), expires)
+        .await
+        .unwrap();
+    assert_eq!(cache.used_bytes(), 28);
+}
+
+#[tokio::test]
+async fn drops_an_entry_replaced_by_an_oversized_value() {
+    let cache = in_memory(100, 30);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_entry(
+            "a".into(),
+            format!("https://{}.com", "a".repeat(30)),
+            expires,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(cache.try_get("a").await, None);
+    assert!(cache.is_empty());
+    assert_eq!(cache.used_bytes(), 0);
+}
+
+#[tokio::test]
+async fn purges_expired_entries() {
+    let cache = in_memory(10, 1024);
+    let now = Utc::now().naive_utc();
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), now + Duration::days(1))
+        .await
+        .unwrap();
+    cache
+        .add_entry(
+            "b".into(),
+            "https://b.com".into(),
+            now + Duration::milliseconds(5),
+        )
+        .await
+        .unwrap();
+    cache
+        .add_entry("c".into(), "https://c.com".into(), now - Duration::days(1))
+        .await
+        .unwrap();
+    assert_eq!(cache.len(), 2);
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(cache.purge_expired().await, 1);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(
+        cache.try_get("a").await,
+        Some(CachedRedirection::Found("https://a.com".to_owned()))
+    );
+}
+
+#[tokio::test]
+async fn removes_entries() {
+    let cache = in_memory(10, 1024);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    cache.remove("a").await.unwrap();
+    // removing an unknown key is not an error
+    cache.remove("b").await.unwrap();
+
+    assert_eq!(cache.try_get("a").await, None);
+    assert_eq!(cache.used_bytes(), 0);
+}
+
+#[tokio::test]
+async fn remembers_missing_keys_until_ttl() {
+    let cache = in_memory(10, 1024);
+
+    cache
+        .add_missing("a".into(), Duration::milliseconds(5))
+        .await
+        .unwrap();
+    assert_eq!(cache.try_get("a").await, Some(CachedRedirection::Missing));
+
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(cache.try_get("a").await, None);
+}
+
+#[tokio::test]
+async fn clears_all_entries() {
+    let cache = in_memory(10, 1024);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_missing("b".into(), Duration::minutes(1))
+        .await
+        .unwrap();
+    cache.clear();
+
+    assert!(cache.is_empty());
+    assert_eq!(cache.used_bytes(), 0);
+}
+
+#[tokio::test]
+async fn counts_hits_misses_and_evictions() {
+    let cache = in_memory(1, 1024);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    // replacing an entry is not an eviction
+    cache
+        .add_entry("a".into(), "https://a.org".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_entry("b".into(), "https://b.com".into(), expires)
+        .await
+        .unwrap();
+    cache.try_get("a").await;
+    cache.try_get("b").await;
+
+    let stats = cache.stats().await.unwrap();
+    assert_eq!(stats.size, 1);
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.evictions, 1);
+}
@@ -0,0 +1,134 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rus_core::prometheus::IntGauge;
+use rus_core::redis::RedisError;
+use rus_core::{Clock, Health};
+
+/// Only moves forward when the test advances it
+#[derive(Debug)]
+struct ManualClock(Mutex<Instant>);
+
+impl ManualClock {
+    fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+fn health() -> (Health, Arc<ManualClock>, IntGauge) {
+    let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    let degraded = IntGauge::new("rus_cache_degraded", "test").unwrap();
+    (
+        Health::new(clock.clone(), degraded.clone()),
+        clock,
+        degraded,
+    )
+}
+
+fn refused() -> RedisError {
+    RedisError::from(io::Error::from(io::ErrorKind::ConnectionRefused))
+}
+
+/// Fails each probe once its backoff elapsed, returns the successive delays
+fn backoff_delays(health: &Health, clock: &ManualClock, probes: usize) -> Vec<Duration> {
+    let step = Duration::from_millis(100);
+    let mut delays = vec![];
+    for _ in 0..probes {
+        let mut waited = Duration::ZERO;
+        while !health.should_try() {
+            clock.advance(step);
+            waited += step;
+        }
+        // a single command probes redis, the others keep waiting
+        assert!(!health.should_try());
+        health.failed(&refused());
+        delays.push(waited);
+    }
+    delays
+}
+
+#[test]
+fn backs_off_exponentially_while_redis_is_down() {
+    let (health, clock, degraded) = health();
+    assert!(health.is_up());
+    assert!(health.should_try());
+    assert_eq!(degraded.get(), 0);
+
+    health.failed(&refused());
+    assert!(!health.is_up());
+    assert_eq!(degraded.get(), 1);
+
+    let delays: Vec<u64> = backoff_delays(&health, &clock, 8)
+        .iter()
+        .map(|delay| delay.as_millis() as u64)
+        .collect();
+    assert_eq!(
+        delays,
+        vec![500, 1_000, 2_000, 4_000, 8_000, 16_000, 30_000, 30_000]
+    );
+    assert_eq!(degraded.get(), 1);
+}
+
+#[test]
+fn recovers_once_a_probe_succeeds() {
+    let (health, clock, degraded) = health();
+    health.failed(&refused());
+    health.failed(&refused());
+    assert!(!health.should_try());
+
+    clock.advance(Duration::from_secs(1));
+    assert!(health.should_try());
+    assert!(health.succeeded());
+    assert!(health.is_up());
+    assert_eq!(degraded.get(), 0);
+    assert!(health.should_try());
+    assert!(health.should_try());
+
+    // only the first success after an outage triggers the recovery
+    assert!(!health.succeeded());
+
+    // the backoff starts over on the next outage
+    health.failed(&refused());
+    assert_eq!(
+        backoff_delays(&health, &clock, 1),
+        vec![Duration::from_millis(500)]
+    );
+}
+
+#[test]
+fn flushes_instead_of_replaying_too_many_invalidations() {
+    let (health, _, _) = health();
+    health.defer_invalidation("a");
+    health.defer_invalidation("a");
+    health.defer_invalidation("b");
+    let pending = health.take_pending();
+    assert_eq!(pending.keys.len(), 2);
+    assert!(!pending.flush);
+    assert!(health.take_pending().is_empty());
+
+    for index in 0..10_000 {
+        health.defer_invalidation(&index.to_string());
+    }
+    assert!(!health.take_pending().flush);
+
+    for index in 0..10_001 {
+        health.defer_invalidation(&index.to_string());
+    }
+    let pending = health.take_pending();
+    assert!(pending.flush);
+    assert!(pending.keys.is_empty());
+
+    // a flush covers the later invalidations
+    health.defer_flush();
+    health.defer_invalidation("a");
+    let pending = health.take_pending();
+    assert!(pending.flush);
+    assert!(pending.keys.is_empty());
+}
@@ -0,0 +1,286 @@
+use std::env;
+use std::num::NonZeroUsize;
+use std::time::Duration as StdDuration;
+
+use ::entity::{audit_event, redirection, redirection_version};
+use chrono::{Duration, Utc};
+use futures_util::StreamExt;
+use rus_core::redis::{self, ConnectionAddr};
+use rus_core::{
+    Actor, Cache, CachedRedirection, CreateMutation, InMemoryCache, Mutation, TieredCache,
+    UpdateMutation,
+};
+use sea_orm::*;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+
+fn redirection(id: i32, short_url: &str, long_url: &str) -> redirection::Model {
+    redirection::Model {
+        id,
+        long_url: long_url.to_owned(),
+        short_url: short_url.to_owned(),
+        creation_date: Default::default(),
+        expiration_date: None,
+        last_access_date: Default::default(),
+        ip_address: "".to_string(),
+        clicks: 0,
+        title: None,
+        notes: None,
+        deleted_at: None,
+    }
+}
+
+/// Row returned by the insertion of a version of the redirection
+fn version(version: i32, redirection: &redirection::Model) -> redirection_version::Model {
+    redirection_version::Model {
+        id: version,
+        redirection_id: redirection.id,
+        version,
+        long_url: redirection.long_url.to_owned(),
+        created_at: Default::default(),
+    }
+}
+
+/// Row returned by the insertion of the audit event recorded along with a mutation
+fn audit_event(redirection: &redirection::Model) -> audit_event::Model {
+    audit_event::Model {
+        id: 1,
+        redirection_id: redirection.id,
+        short_url: redirection.short_url.to_owned(),
+        action: "".to_owned(),
+        actor_type: "".to_owned(),
+        actor: "".to_owned(),
+        before: None,
+        after: None,
+        created_at: Default::default(),
+    }
+}
+
+async fn cache_with(entries: &[(&str, &str)]) -> InMemoryCache {
+    let cache = InMemoryCache::new(NonZeroUsize::new(10).unwrap(), 1024);
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+    for (short_url, long_url) in entries {
+        cache
+            .add_entry(short_url.to_string(), long_url.to_string(), expires)
+            .await
+            .unwrap();
+    }
+    cache
+}
+
+#[tokio::test]
+async fn update_invalidates_cache() {
+    let cache = cache_with(&[("abcdef", "https://example.com/old")]).await;
+    let db = MockDatabase::new(DatabaseBackend::Postgres)
+        .append_query_results(vec![
+            vec![redirection(1, "abcdef", "https://example.com/old")],
+            vec![redirection(1, "abcdef", "https://example.com/new")],
+        ])
+        .append_query_results(vec![
+            vec![version(
+                1,
+                &redirection(1, "abcdef", "https://example.com/old"),
+            )],
+            vec![version(
+                2,
+                &redirection(1, "abcdef", "https://example.com/new"),
+            )],
+        ])
+        .append_query_results(vec![vec![audit_event(&redirection(
+            1,
+            "abcdef",
+            "https://example.com/new",
+        ))]])
+        .into_connection();
+
+    Mutation::update_redirection_by_id(
+        &db,
+        &cache,
+        &Actor::System,
+        UpdateMutation::new(1, "https://example.com/new".to_owned()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(cache.try_get("abcdef").await, None);
+}
+
+#[tokio::test]
+async fn delete_invalidates_cache() {
+    let cache = cache_with(&[
+        ("abcdef", "https://example.com/deleted"),
+        ("ghijkl", "https://example.com/kept"),
+    ])
+    .await;
+    let db = MockDatabase::new(DatabaseBackend::Postgres)
+        .append_query_results(vec![
+            vec![redirection(1, "abcdef", "https://example.com/deleted")],
+            vec![redirection::Model {
+                deleted_at: Some(Utc::now().naive_utc()),
+                ..redirection(1, "abcdef", "https://example.com/deleted")
+            }],
+        ])
+        .append_query_results(vec![vec![audit_event(&redirection(
+            1,
+            "abcdef",
+            "https://example.com/deleted",
+        ))]])
+        .into_connection();
+
+    Mutation::delete_redirection(&db, &cache, &Actor::System, 1)
+        .await
+        .unwrap();
+
+    assert_eq!(cache.try_get("abcdef").await, None);
+    assert_eq!(
+        cache.try_get("ghijkl").await,
+        Some(CachedRedirection::Found(
+            "https://example.com/kept".to_owned()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn expiry_invalidates_cache() {
+    let cache = cache_with(&[
+        ("abcdef", "https://example.com/expired"),
+        ("ghijkl", "https://example.com/active"),
+    ])
+    .await;
+    let db = MockDatabase::new(DatabaseBackend::Postgres)
+        .append_query_results(vec![vec![redirection(
+            1,
+            "abcdef",
+            "https://example.com/expired",
+        )]])
+        .append_exec_results(vec![MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        }])
+        .append_query_results(vec![vec![audit_event(&redirection(
+            1,
+            "abcdef",
+            "https://example.com/expired",
+        ))]])
+        .into_connection();
+
+    let expired = Mutation::remove_expired_redirections(&db, &cache)
+        .await
+        .unwrap();
+
+    assert_eq!(expired.len(), 1);
+    assert_eq!(cache.try_get("abcdef").await, None);
+    assert_eq!(
+        cache.try_get("ghijkl").await,
+        Some(CachedRedirection::Found(
+            "https://example.com/active".to_owned()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn create_clears_missing_entry() {
+    let cache = cache_with(&[]).await;
+    cache
+        .add_missing("abcdef".to_owned(), Duration::minutes(1))
+        .await
+        .unwrap();
+    let db = MockDatabase::new(DatabaseBackend::Postgres)
+        .append_query_results(vec![
+            vec![],
+            vec![redirection(1, "abcdef", "https://example.com/new")],
+        ])
+        .append_query_results(vec![vec![version(
+            1,
+            &redirection(1, "abcdef", "https://example.com/new"),
+        )]])
+        .append_query_results(vec![vec![audit_event(&redirection(
+            1,
+            "abcdef",
+            "https://example.com/new",
+        ))]])
+        .into_connection();
+
+    Mutation::create_redirection(
+        &db,
+        &cache,
+        &Actor::System,
+        CreateMutation::new(
+            "https://example.com/new".to_owned(),
+            "127.0.0.1".to_owned(),
+            Duration::days(1),
+        ),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(cache.try_get("abcdef").await, None);
+}
+
+/// Forwards the connections to redis, until aborted to cut the cache off from redis
+async fn forward(listener: TcpListener, target: String) {
+    let mut connections = JoinSet::new();
+    while let Ok((mut inbound, _)) = listener.accept().await {
+        let target = target.clone();
+        connections.spawn(async move {
+            if let Ok(mut outbound) = TcpStream::connect(target).await {
+                tokio::io::copy_bidirectional(&mut inbound, &mut outbound)
+                    .await
+                    .ok();
+            }
+        });
+    }
+}
+
+/// Needs a redis server, skipped unless `RUS_TEST_REDIS_URL` is set
+#[tokio::test]
+async fn publishes_the_invalidations_missed_while_redis_is_down() {
+    let Ok(url) = env::var("RUS_TEST_REDIS_URL") else {
+        eprintln!("RUS_TEST_REDIS_URL is not set, skipping");
+        return;
+    };
+    let info = redis::IntoConnectionInfo::into_connection_info(url.as_str()).unwrap();
+    let ConnectionAddr::Tcp(host, port) = &info.addr else {
+        eprintln!("RUS_TEST_REDIS_URL isn't a tcp url, skipping");
+        return;
+    };
+    let target = format!("{}:{}", host, port);
+
+    let mut subscriber = redis::Client::open(info.clone())
+        .unwrap()
+        .get_async_connection()
+        .await
+        .unwrap()
+        .into_pubsub();
+    subscriber.subscribe("rus:invalidations").await.unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(forward(listener, target.clone()));
+    let cache = TieredCache::connect(
+        redis::Client::open(format!("redis://{}/5", address)).unwrap(),
+        StdDuration::from_millis(200),
+        InMemoryCache::new(NonZeroUsize::new(10).unwrap(), 1024),
+        Duration::seconds(10),
+    )
+    .await
+    .unwrap();
+
+    proxy.abort();
+    proxy.await.ok();
+    cache.remove("abcdef").await.unwrap();
+
+    tokio::spawn(forward(TcpListener::bind(address).await.unwrap(), target));
+    let mut messages = subscriber.on_message();
+    let mut published = None;
+    // the lookups probe redis each time the backoff elapses, the first one to succeed publishes
+    for _ in 0..20 {
+        cache.try_get("other").await;
+        let next = tokio::time::timeout(StdDuration::from_millis(500), messages.next()).await;
+        if let Ok(Some(message)) = next {
+            published = Some(message.get_payload::<String>().unwrap());
+            break;
+        }
+    }
+    assert_eq!(published.as_deref(), Some("abcdef"));
+}
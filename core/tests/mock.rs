@@ -1,11 +1,16 @@
+use std::num::NonZeroUsize;
+
+use chrono::Duration;
 use prepare::prepare_mock_db;
-use rus_core::{CreateMutation, Mutation, Query, UpdateMutation};
+use rus_core::{Actor, CreateMutation, InMemoryCache, Mutation, Query, UpdateMutation};
 
 mod prepare;
 
 #[tokio::test]
 async fn main() {
     let db = &prepare_mock_db();
+    let cache = &InMemoryCache::new(NonZeroUsize::new(10).unwrap(), 1024);
+    let actor = &Actor::ApiKey("admin".to_owned());
 
     {
         let redirection = Query::find_redirection_by_id(db, 1).await.unwrap().unwrap();
@@ -22,7 +27,13 @@ async fn main() {
     {
         let redirection = Mutation::create_redirection(
             db,
-            CreateMutation::new("https://example.com/created".to_string(), "".to_string()),
+            cache,
+            actor,
+            CreateMutation::new(
+                "https://example.com/created".to_string(),
+                "".to_string(),
+                Duration::days(1),
+            ),
         )
         .await
         .unwrap();
@@ -36,7 +47,9 @@ async fn main() {
     {
         let redirection = Mutation::update_redirection_by_id(
             db,
-            UpdateMutation::new(1, "https://example.com/created".to_string()),
+            cache,
+            actor,
+            UpdateMutation::new(1, "https://example.com/updated".to_string()),
         )
         .await
         .unwrap();
@@ -48,14 +61,18 @@ async fn main() {
     }
 
     {
-        let result = Mutation::delete_redirection(db, 5).await.unwrap();
+        let redirection = Mutation::delete_redirection(db, cache, actor, 5)
+            .await
+            .unwrap();
 
-        assert_eq!(result.rows_affected, 1);
+        assert!(redirection.deleted_at.is_some());
     }
 
     {
-        let result = Mutation::delete_all_redirections(db).await.unwrap();
+        let deleted = Mutation::delete_all_redirections(db, cache, actor)
+            .await
+            .unwrap();
 
-        assert_eq!(result.rows_affected, 5);
+        assert_eq!(deleted, 5);
     }
 }
@@ -1,74 +1,101 @@
-use ::entity::redirection;
+#![cfg(feature = "mock")]
+
+use ::entity::{audit_event, redirection, redirection_version};
 use sea_orm::*;
 
-#[cfg(feature = "mock")]
+fn redirection(id: i32, short_url: &str, long_url: &str) -> redirection::Model {
+    redirection::Model {
+        id,
+        long_url: long_url.to_owned(),
+        short_url: short_url.to_owned(),
+        creation_date: Default::default(),
+        expiration_date: None,
+        last_access_date: Default::default(),
+        ip_address: "".to_string(),
+        clicks: 0,
+        title: None,
+        notes: None,
+        deleted_at: None,
+    }
+}
+
+fn trashed(redirection: redirection::Model) -> redirection::Model {
+    redirection::Model {
+        deleted_at: Some(Default::default()),
+        ..redirection
+    }
+}
+
+/// Row returned by the insertion of a version of the redirection
+pub fn version(version: i32, redirection: &redirection::Model) -> redirection_version::Model {
+    redirection_version::Model {
+        id: version,
+        redirection_id: redirection.id,
+        version,
+        long_url: redirection.long_url.to_owned(),
+        created_at: Default::default(),
+    }
+}
+
+/// Row returned by the insertion of an audit event
+pub fn audit_event(id: i32, redirection: &redirection::Model) -> audit_event::Model {
+    audit_event::Model {
+        id,
+        redirection_id: redirection.id,
+        short_url: redirection.short_url.to_owned(),
+        action: "".to_owned(),
+        actor_type: "".to_owned(),
+        actor: "".to_owned(),
+        before: None,
+        after: None,
+        created_at: Default::default(),
+    }
+}
+
+/// Results of the queries run by `mock.rs`, in order
 pub fn prepare_mock_db() -> DatabaseConnection {
-    MockDatabase::new(DatabaseBackend::Postgres)
+    let created = redirection(6, "fffff", "https://example.com/created");
+    let updated = redirection(1, "abcde", "https://example.com/updated");
+    let deleted = trashed(redirection(5, "eeeee", "https://example.com/"));
+    let all = vec![
+        updated.clone(),
+        redirection(2, "ggggg", "https://example.com/"),
+        redirection(3, "hhhhh", "https://example.com/"),
+        redirection(4, "iiiii", "https://example.com/"),
+        created.clone(),
+    ];
+
+    let mut db = MockDatabase::new(DatabaseBackend::Postgres)
         .append_query_results(vec![
-            vec![redirection::Model {
-                id: 1,
-                long_url: "https://example.com/".to_owned(),
-                short_url: "abcde".to_owned(),
-                creation_date: Default::default(),
-                expiration_date: None,
-                last_access_date: Default::default(),
-                ip_address: "".to_string(),
-            }],
-            vec![redirection::Model {
-                id: 5,
-                long_url: "https://example.com/".to_owned(),
-                short_url: "eeeee".to_owned(),
-                creation_date: Default::default(),
-                expiration_date: None,
-                last_access_date: Default::default(),
-                ip_address: "".to_string(),
-            }],
-            vec![redirection::Model {
-                id: 6,
-                long_url: "https://example.com/".to_owned(),
-                short_url: "fffff".to_owned(),
-                creation_date: Default::default(),
-                expiration_date: None,
-                last_access_date: Default::default(),
-                ip_address: "".to_string(),
-            }],
-            vec![redirection::Model {
-                id: 1,
-                long_url: "https://example.com/".to_owned(),
-                short_url: "ggggg".to_owned(),
-                creation_date: Default::default(),
-                expiration_date: None,
-                last_access_date: Default::default(),
-                ip_address: "".to_string(),
-            }],
-            vec![redirection::Model {
-                id: 1,
-                long_url: "https://example.com/".to_owned(),
-                short_url: "hhhhh".to_owned(),
-                creation_date: Default::default(),
-                expiration_date: None,
-                last_access_date: Default::default(),
-                ip_address: "".to_string(),
-            }],
-            vec![redirection::Model {
-                id: 5,
-                long_url: "https://example.com/created".to_owned(),
-                short_url: "iiiiii".to_owned(),
-                creation_date: Default::default(),
-                expiration_date: None,
-                last_access_date: Default::default(),
-                ip_address: "".to_string(),
-            }],
+            // find by id
+            vec![redirection(1, "abcde", "https://example.com/")],
+            vec![redirection(5, "eeeee", "https://example.com/")],
+            // create: the short url is free, then the insertion and its first version
+            vec![],
+            vec![created.clone()],
         ])
-        .append_exec_results(vec![
-            MockExecResult {
-                last_insert_id: 6,
-                rows_affected: 1,
-            },
-            MockExecResult {
-                last_insert_id: 6,
-                rows_affected: 5,
-            },
+        .append_query_results(vec![vec![version(1, &created)]])
+        .append_query_results(vec![vec![audit_event(1, &created)]])
+        // update: the lookup, the update, then the last version and the new one
+        .append_query_results(vec![
+            vec![redirection(1, "abcde", "https://example.com/")],
+            vec![updated.clone()],
+        ])
+        .append_query_results(vec![vec![version(1, &updated)], vec![version(2, &updated)]])
+        .append_query_results(vec![vec![audit_event(2, &updated)]])
+        // delete: the lookup, then the move to the trash
+        .append_query_results(vec![
+            vec![redirection(5, "eeeee", "https://example.com/")],
+            vec![deleted.clone()],
         ])
-        .into_connection()
+        .append_query_results(vec![vec![audit_event(3, &deleted)]])
+        // delete all: every redirection is moved to the trash one by one
+        .append_query_results(vec![all.clone()]);
+    for (id, redirection) in (4..).zip(all) {
+        let redirection = trashed(redirection);
+        db = db
+            .append_query_results(vec![vec![redirection.clone()]])
+            .append_query_results(vec![vec![audit_event(id, &redirection)]]);
+    }
+    db.into_connection()
 }
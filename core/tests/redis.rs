@@ -0,0 +1,106 @@
+use std::env;
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use redis::aio::Connection;
+use rus_core::{Cache, CachedRedirection, RedisCache};
+
+/// The redis tests need a server, they are skipped unless `RUS_TEST_REDIS_URL` is set.
+/// Each test works in its own database, emptied first
+async fn redis(db: i64) -> Option<(RedisCache, Connection)> {
+    let url = match env::var("RUS_TEST_REDIS_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("RUS_TEST_REDIS_URL is not set, skipping");
+            return None;
+        }
+    };
+    let mut info = redis::IntoConnectionInfo::into_connection_info(url.as_str()).unwrap();
+    info.redis.db = db;
+    let client = redis::Client::open(info).unwrap();
+
+    let mut connection = client.get_async_connection().await.unwrap();
+    redis::cmd("FLUSHDB")
+        .query_async::<_, ()>(&mut connection)
+        .await
+        .unwrap();
+    let cache = RedisCache::connect(client, StdDuration::from_secs(1))
+        .await
+        .unwrap();
+    Some((cache, connection))
+}
+
+async fn keys(connection: &mut Connection) -> Vec<String> {
+    let mut keys: Vec<String> = redis::cmd("KEYS")
+        .arg("*")
+        .query_async(connection)
+        .await
+        .unwrap();
+    keys.sort();
+    keys
+}
+
+#[tokio::test]
+async fn doesnt_cache_without_a_positive_ttl() {
+    let Some((cache, mut connection)) = redis(1).await else {
+        return;
+    };
+    let now = Utc::now().naive_utc();
+
+    cache
+        .add_entry(
+            "expired".into(),
+            "https://a.com".into(),
+            now - Duration::days(1),
+        )
+        .await
+        .unwrap();
+    cache
+        .add_entry("ending".into(), "https://a.com".into(), now)
+        .await
+        .unwrap();
+    cache
+        .add_missing("missing".into(), Duration::zero())
+        .await
+        .unwrap();
+    assert!(keys(&mut connection).await.is_empty());
+
+    cache
+        .add_entry("forever".into(), "https://a.com".into(), NaiveDateTime::MAX)
+        .await
+        .unwrap();
+    assert_eq!(
+        cache.try_get("forever").await,
+        Some(CachedRedirection::Found("https://a.com".to_owned()))
+    );
+}
+
+#[tokio::test]
+async fn flushes_only_the_cache_keys() {
+    let Some((cache, mut connection)) = redis(2).await else {
+        return;
+    };
+    let expires = Utc::now().naive_utc() + Duration::days(1);
+    cache
+        .add_entry("a".into(), "https://a.com".into(), expires)
+        .await
+        .unwrap();
+    cache
+        .add_missing("b".into(), Duration::minutes(1))
+        .await
+        .unwrap();
+    redis::cmd("SET")
+        .arg("rus:jobs:trash_purge")
+        .arg("replica")
+        .query_async::<_, ()>(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(
+        keys(&mut connection).await,
+        vec!["rus:cache:a", "rus:cache:b", "rus:jobs:trash_purge"]
+    );
+
+    cache.flush().await.unwrap();
+    assert_eq!(keys(&mut connection).await, vec!["rus:jobs:trash_purge"]);
+    assert_eq!(cache.try_get("a").await, None);
+}
@@ -0,0 +1,36 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Change made to a redirection, kept even once the redirection is purged
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, ToSchema)]
+#[schema(as = AuditEvent)]
+#[sea_orm(table_name = "audit_event")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    pub redirection_id: i32,
+    pub short_url: String,
+    /// `create`, `update`, `rollback`, `delete`, `restore`, `expire` or `purge`
+    pub action: String,
+    /// `api_key`, `ip` or `system`
+    pub actor_type: String,
+    /// Name of the API key or ip address, empty for the system
+    pub actor: String,
+    /// The redirection before the change, missing for a creation
+    #[sea_orm(nullable)]
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<Json>,
+    /// The redirection after the change, missing for a purge
+    #[sea_orm(nullable)]
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<Json>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
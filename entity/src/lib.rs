@@ -1 +1,3 @@
+pub mod audit_event;
 pub mod redirection;
+pub mod redirection_version;
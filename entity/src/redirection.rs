@@ -1,8 +1,10 @@
 use chrono::NaiveDateTime;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, ToSchema)]
+#[schema(as = Redirection)]
 #[sea_orm(table_name = "redirection")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -17,6 +19,15 @@ pub struct Model {
     pub expiration_date: Option<NaiveDateTime>,
     pub last_access_date: NaiveDateTime,
     pub ip_address: String,
+    pub clicks: i64,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub title: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub notes: Option<String>,
+    /// Set while the redirection is in the trash
+    #[sea_orm(nullable)]
+    #[serde(skip_deserializing)]
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,25 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Destination of a redirection from a given change on, the last version is the current one
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, ToSchema)]
+#[schema(as = RedirectionVersion)]
+#[sea_orm(table_name = "redirection_version")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    pub redirection_id: i32,
+    /// Starts at 1 with the creation of the redirection
+    pub version: i32,
+    #[sea_orm(column_type = "Text")]
+    pub long_url: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,14 +1,26 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20221110_195452_create_redirection_table;
+mod m20221203_101500_add_redirection_clicks;
+mod m20221210_143000_add_redirection_search;
+mod m20221215_090000_add_redirection_deleted_at;
+mod m20221218_100000_create_audit_event_table;
+mod m20221220_080000_create_redirection_version_table;
+mod m20221222_120000_widen_redirection_long_url;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(
-            m20221110_195452_create_redirection_table::Migration,
-        )]
+        vec![
+            Box::new(m20221110_195452_create_redirection_table::Migration),
+            Box::new(m20221203_101500_add_redirection_clicks::Migration),
+            Box::new(m20221210_143000_add_redirection_search::Migration),
+            Box::new(m20221215_090000_add_redirection_deleted_at::Migration),
+            Box::new(m20221218_100000_create_audit_event_table::Migration),
+            Box::new(m20221220_080000_create_redirection_version_table::Migration),
+            Box::new(m20221222_120000_widen_redirection_long_url::Migration),
+        ]
     }
 }
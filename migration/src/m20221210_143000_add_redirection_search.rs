@@ -0,0 +1,86 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // sqlite only supports a single change per ALTER TABLE
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .add_column(ColumnDef::new(Redirection::Title).text().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .add_column(ColumnDef::new(Redirection::Notes).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Other backends fall back to LIKE queries, full-text search is only indexed on postgres
+        if manager.get_database_backend() == DbBackend::Postgres {
+            let db = manager.get_connection();
+            db.execute(Statement::from_string(
+                DbBackend::Postgres,
+                r#"ALTER TABLE redirection ADD COLUMN search_vector tsvector
+                GENERATED ALWAYS AS (to_tsvector('simple',
+                    coalesce(long_url, '') || ' ' || coalesce(short_url, '') || ' ' ||
+                    coalesce(title, '') || ' ' || coalesce(notes, ''))) STORED"#
+                    .to_owned(),
+            ))
+            .await?;
+            db.execute(Statement::from_string(
+                DbBackend::Postgres,
+                "CREATE INDEX idx_redirection_search ON redirection USING GIN (search_vector)"
+                    .to_owned(),
+            ))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() == DbBackend::Postgres {
+            manager
+                .get_connection()
+                .execute(Statement::from_string(
+                    DbBackend::Postgres,
+                    "ALTER TABLE redirection DROP COLUMN search_vector".to_owned(),
+                ))
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .drop_column(Redirection::Notes)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .drop_column(Redirection::Title)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Redirection {
+    Table,
+    Title,
+    Notes,
+}
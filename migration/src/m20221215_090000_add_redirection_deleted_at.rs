@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .add_column(ColumnDef::new(Redirection::DeletedAt).date_time().null())
+                    .to_owned(),
+            )
+            .await?;
+        // every lookup filters the trashed links out
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_redirection_deleted_at")
+                    .table(Redirection::Table)
+                    .col(Redirection::DeletedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_redirection_deleted_at")
+                    .table(Redirection::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .drop_column(Redirection::DeletedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Redirection {
+    Table,
+    DeletedAt,
+}
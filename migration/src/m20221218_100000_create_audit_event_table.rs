@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // no foreign key, the events outlive the purged redirections
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuditEvent::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AuditEvent::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AuditEvent::RedirectionId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(AuditEvent::ShortUrl).string().not_null())
+                    .col(ColumnDef::new(AuditEvent::Action).string().not_null())
+                    .col(ColumnDef::new(AuditEvent::ActorType).string().not_null())
+                    .col(ColumnDef::new(AuditEvent::Actor).string().not_null())
+                    .col(ColumnDef::new(AuditEvent::Before).json().null())
+                    .col(ColumnDef::new(AuditEvent::After).json().null())
+                    .col(ColumnDef::new(AuditEvent::CreatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_event_redirection_id")
+                    .table(AuditEvent::Table)
+                    .col(AuditEvent::RedirectionId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_event_created_at")
+                    .table(AuditEvent::Table)
+                    .col(AuditEvent::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuditEvent::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum AuditEvent {
+    Table,
+    Id,
+    RedirectionId,
+    ShortUrl,
+    Action,
+    ActorType,
+    Actor,
+    Before,
+    After,
+    CreatedAt,
+}
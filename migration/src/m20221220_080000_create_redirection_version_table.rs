@@ -0,0 +1,106 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::ConnectionTrait;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RedirectionVersion::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RedirectionVersion::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RedirectionVersion::RedirectionId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RedirectionVersion::Version)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RedirectionVersion::LongUrl)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RedirectionVersion::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_redirection_version_redirection_id_version")
+                    .table(RedirectionVersion::Table)
+                    .col(RedirectionVersion::RedirectionId)
+                    .col(RedirectionVersion::Version)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // the existing redirections start their history with their current destination
+        let backfill = Query::insert()
+            .into_table(RedirectionVersion::Table)
+            .columns([
+                RedirectionVersion::RedirectionId,
+                RedirectionVersion::Version,
+                RedirectionVersion::LongUrl,
+                RedirectionVersion::CreatedAt,
+            ])
+            .select_from(
+                Query::select()
+                    .column(Redirection::Id)
+                    .expr(Expr::val(1))
+                    .column(Redirection::LongUrl)
+                    .column(Redirection::CreationDate)
+                    .from(Redirection::Table)
+                    .to_owned(),
+            )
+            .map_err(|err| DbErr::Migration(err.to_string()))?
+            .to_owned();
+        let db = manager.get_connection();
+        db.execute(db.get_database_backend().build(&backfill))
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RedirectionVersion::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum RedirectionVersion {
+    Table,
+    Id,
+    RedirectionId,
+    Version,
+    LongUrl,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Redirection {
+    Table,
+    Id,
+    LongUrl,
+    CreationDate,
+}
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DbBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A string is a VARCHAR(255) on mysql, too short for the urls the policies accept.
+        // It has no length on postgres and sqlite, which can't modify a column anyway
+        if manager.get_database_backend() != DbBackend::MySql {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .modify_column(ColumnDef::new(Redirection::LongUrl).text().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DbBackend::MySql {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Redirection::Table)
+                    .modify_column(ColumnDef::new(Redirection::LongUrl).string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Redirection {
+    Table,
+    LongUrl,
+}